@@ -1,4 +1,5 @@
 fn main() {
     let _build = cxx_build::bridge("src/lib.rs");
     println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=log_callback.hpp");
 }
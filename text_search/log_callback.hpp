@@ -0,0 +1,50 @@
+#pragma once
+
+#include <cstdint>
+#include <functional>
+#include <memory>
+#include <string>
+
+namespace mgcxx::text_search {
+
+// NOTE: Values are checked against the Rust side definition by cxx.
+enum class LogLevel : uint8_t {
+  Error = 1,
+  Warn = 2,
+  Info = 3,
+  Debug = 4,
+  Trace = 5,
+};
+
+// Host application logging callback passed to init. All logs coming from
+// the text search engine (mgcxx and tantivy) are routed to it.
+// NOTE: The function is called from multiple threads (e.g. tantivy merging
+// threads) -> it has to be thread-safe. It's allowed to call init (e.g. to
+// change the log level). Exceptions thrown by it are swallowed because they
+// can't unwind through the Rust code.
+class LogCallback {
+public:
+  using Function =
+      std::function<void(LogLevel level, const std::string &target,
+                         const std::string &message)>;
+
+  explicit LogCallback(Function function) : function_(std::move(function)) {}
+
+  void log(LogLevel level, const std::string &target,
+           const std::string &message) const noexcept {
+    try {
+      function_(level, target, message);
+    } catch (...) {
+    }
+  }
+
+private:
+  Function function_;
+};
+
+inline std::unique_ptr<LogCallback> make_log_callback(
+    LogCallback::Function function) {
+  return std::make_unique<LogCallback>(std::move(function));
+}
+
+} // namespace mgcxx::text_search
@@ -0,0 +1,117 @@
+//! Commits running in the background, so the caller doesn't wait for the segments to be flushed
+//! and the reader to be reloaded.
+
+use std::io::Error;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use crate::{commit_writer, ffi, TantivyContext};
+
+#[derive(Default)]
+struct CommitState {
+    /// None while the commit is running.
+    result: Mutex<Option<Result<u64, String>>>,
+    done: Condvar,
+}
+
+pub struct CommitHandle {
+    state: Arc<CommitState>,
+}
+
+impl CommitHandle {
+    pub fn is_done(&self) -> bool {
+        match self.state.result.lock() {
+            Ok(result) => result.is_some(),
+            Err(_) => true,
+        }
+    }
+
+    pub fn wait(&self) -> Result<u64, std::io::Error> {
+        let mut result = match self.state.result.lock() {
+            Ok(r) => r,
+            Err(_) => return Err(Error::other("commit state is poisoned")),
+        };
+        loop {
+            match result.as_ref() {
+                Some(Ok(opstamp)) => return Ok(*opstamp),
+                Some(Err(e)) => return Err(Error::other(e.clone())),
+                None => {}
+            }
+            result = match self.state.done.wait(result) {
+                Ok(r) => r,
+                Err(_) => return Err(Error::other("commit state is poisoned")),
+            };
+        }
+    }
+
+    pub fn error(&self) -> String {
+        match self.state.result.lock() {
+            Ok(result) => match result.as_ref() {
+                Some(Err(e)) => e.clone(),
+                _ => String::new(),
+            },
+            Err(_) => "commit state is poisoned".to_string(),
+        }
+    }
+}
+
+pub fn commit_async(context: &mut ffi::Context) -> Result<Box<CommitHandle>, std::io::Error> {
+    let tantivy_context = &mut context.tantivyContext;
+    tantivy_context
+        .async_commits
+        .retain(|thread| !thread.is_finished());
+    let state = Arc::new(CommitState::default());
+    let thread_state = state.clone();
+    let writer = tantivy_context.writer.clone();
+    let index_reader = tantivy_context.index_reader.clone();
+    let index_path = tantivy_context.index_path.clone();
+    let (locked_sender, locked_receiver) = mpsc::channel();
+    let thread = std::thread::Builder::new()
+        .name("mgcxx-commit".to_string())
+        .spawn(move || {
+            // NOTE: If locking fails (or the commit panics), dropping the sender unblocks the
+            // caller as well.
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                writer.lock(&index_path).and_then(|mut state| {
+                    let _ = locked_sender.send(());
+                    commit_writer(&mut state, &index_reader, &index_path)
+                })
+            }));
+            let result = match result {
+                Ok(r) => r.map_err(|e| e.to_string()),
+                Err(_) => Err(format!(
+                    "Commit thread of {:?} text search index panicked",
+                    index_path
+                )),
+            };
+            // NOTE: The waiters have to be woken up whatever happened.
+            *thread_state
+                .result
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(result);
+            thread_state.done.notify_all();
+        });
+    let thread = match thread {
+        Ok(t) => t,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to start the commit thread of {:?} text search index -> {}",
+                tantivy_context.index_path, e
+            )));
+        }
+    };
+    // NOTE: Once the thread holds the writer, operations called after this one wait for the
+    // commit.
+    let _ = locked_receiver.recv();
+    tantivy_context.async_commits.push(thread);
+    Ok(Box::new(CommitHandle { state }))
+}
+
+/// Waits for all the commits started by commit_async (e.g. before the writer is closed).
+pub fn wait_all(tantivy_context: &mut TantivyContext) {
+    for thread in tantivy_context.async_commits.drain(..) {
+        // NOTE: Errors are reported through the handles.
+        let _ = thread.join();
+    }
+}
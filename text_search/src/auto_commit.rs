@@ -0,0 +1,160 @@
+//! Background thread committing the pending operations (enabled by the auto_commit setting), so
+//! that callers batching operations (skip_commit) don't need their own timer.
+
+use log::{debug, error};
+use serde::Deserialize;
+use std::io::Error;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tantivy::IndexReader;
+
+use crate::{commit_writer, IndexWriterState, SharedIndexWriter, TantivyContext};
+
+/// Delay before a failed commit is retried.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutoCommitSettings {
+    /// Commit once there are max_pending pending operations, 0 means no limit.
+    #[serde(default)]
+    max_pending: u64,
+    /// Commit once the oldest pending operation is interval_ms old, 0 means no limit.
+    #[serde(default)]
+    interval_ms: u64,
+}
+
+impl AutoCommitSettings {
+    pub fn check(&self, index_path: &str) -> Result<(), std::io::Error> {
+        if self.max_pending == 0 && self.interval_ms == 0 {
+            return Err(Error::other(format!(
+                "auto_commit setting of text search index at {} needs max_pending or interval_ms",
+                index_path
+            )));
+        }
+        Ok(())
+    }
+
+    /// Time left until the oldest pending operation is interval_ms old, None means there is no
+    /// deadline.
+    fn remaining(&self, state: &IndexWriterState) -> Option<Duration> {
+        if self.interval_ms == 0 {
+            return None;
+        }
+        state
+            .pending_since
+            .map(|since| Duration::from_millis(self.interval_ms).saturating_sub(since.elapsed()))
+    }
+
+    fn is_due(&self, state: &IndexWriterState) -> bool {
+        if state.pending_operations == 0 {
+            return false;
+        }
+        (self.max_pending != 0 && state.pending_operations >= self.max_pending)
+            || self.remaining(state) == Some(Duration::ZERO)
+    }
+}
+
+pub struct AutoCommit {
+    writer: Arc<SharedIndexWriter>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for AutoCommit {
+    fn drop(&mut self) {
+        // NOTE: The thread has to stop even if an operation on the writer panicked, otherwise
+        // the join below would wait forever.
+        let mut state = self.writer.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.auto_commit_stopped = true;
+        drop(state);
+        self.writer.pending_changed.notify_all();
+        if let Some(thread) = self.thread.take() {
+            // NOTE: Failed commits are only logged, the thread itself doesn't fail.
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Called (with the writer locked) for each operation left pending.
+pub fn operation_pending(tantivy_context: &TantivyContext, state: &mut IndexWriterState) {
+    state.pending_operations += 1;
+    state.pending_since.get_or_insert_with(Instant::now);
+    if let Some(settings) = &tantivy_context.settings.auto_commit {
+        // NOTE: The first pending operation starts the interval.
+        if state.pending_operations == 1 || settings.is_due(state) {
+            tantivy_context.writer.pending_changed.notify_one();
+        }
+    }
+}
+
+pub fn start(tantivy_context: &TantivyContext) -> Result<Option<AutoCommit>, std::io::Error> {
+    let settings = match &tantivy_context.settings.auto_commit {
+        Some(s) => s.clone(),
+        None => return Ok(None),
+    };
+    let writer = tantivy_context.writer.clone();
+    let index_reader = tantivy_context.index_reader.clone();
+    let index_path = tantivy_context.index_path.clone();
+    let thread_writer = writer.clone();
+    let thread = std::thread::Builder::new()
+        .name("mgcxx-auto-commit".to_string())
+        .spawn(move || run(&thread_writer, &index_reader, &index_path, &settings));
+    let thread = match thread {
+        Ok(t) => t,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to start the auto-commit thread of {:?} text search index -> {}",
+                tantivy_context.index_path, e
+            )));
+        }
+    };
+    Ok(Some(AutoCommit {
+        writer,
+        thread: Some(thread),
+    }))
+}
+
+fn run(
+    writer: &SharedIndexWriter,
+    index_reader: &IndexReader,
+    index_path: &std::path::Path,
+    settings: &AutoCommitSettings,
+) {
+    let mut state = match writer.state.lock() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    while !state.auto_commit_stopped {
+        let timeout = if settings.is_due(&state) {
+            let pending_operations = state.pending_operations;
+            match commit_writer(&mut state, index_reader, index_path) {
+                Ok(_) => {
+                    debug!(
+                        "Auto-committed {} operations of {:?} text search index",
+                        pending_operations, index_path
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    error!("Auto-commit failed -> {}", e);
+                    Some(RETRY_INTERVAL)
+                }
+            }
+        } else {
+            settings.remaining(&state)
+        };
+        let next_state = match timeout {
+            Some(t) => writer
+                .pending_changed
+                .wait_timeout(state, t)
+                .ok()
+                .map(|(s, _)| s),
+            None => writer.pending_changed.wait(state).ok(),
+        };
+        state = match next_state {
+            Some(s) => s,
+            None => return,
+        };
+    }
+}
@@ -0,0 +1,294 @@
+//! Interrupting long running searches, either by a cancellation token or by a timeout
+//! ([crate::ffi::SearchInput::timeout_ms]).
+
+use std::io::Error;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::query::{AutomatonWeight, EnableScoring, Query, Weight};
+use tantivy::schema::Field;
+use tantivy::{
+    DocId, DocSet, Searcher, SegmentOrdinal, SegmentReader, COLLECT_BLOCK_BUFFER_LEN, TERMINATED,
+};
+use tantivy_fst::{Automaton, Regex};
+
+use crate::ffi;
+
+/// Number of documents collected between two checks of the interruption.
+const CHECK_INTERVAL: u32 = 1024;
+
+#[derive(Default)]
+pub struct CancellationToken {
+    /// Shared with the searches (the queries can't borrow the token).
+    cancelled: Arc<AtomicBool>,
+}
+
+pub fn create_cancellation_token() -> Box<CancellationToken> {
+    Box::new(CancellationToken::default())
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Interruption conditions of a single search call, the clones share the interrupted state.
+#[derive(Clone, Debug)]
+pub struct Interruption {
+    token: Option<Arc<AtomicBool>>,
+    deadline: Option<Instant>,
+    /// Set once a segment is not collected fully.
+    interrupted: Arc<AtomicBool>,
+}
+
+impl Interruption {
+    pub fn new(input: &ffi::SearchInput, token: Option<&CancellationToken>) -> Interruption {
+        let deadline = if input.timeout_ms == 0 {
+            None
+        } else {
+            Some(Instant::now() + Duration::from_millis(input.timeout_ms))
+        };
+        Interruption {
+            token: token.map(|t| t.cancelled.clone()),
+            deadline,
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.token.is_some() || self.deadline.is_some()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token
+            .as_ref()
+            .is_some_and(|t| t.load(Ordering::Relaxed))
+    }
+
+    fn should_stop(&self) -> bool {
+        if self.is_cancelled() {
+            return true;
+        }
+        self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// Checks the interruption, remembering that the search didn't run fully.
+    fn stop(&self) -> bool {
+        if self.should_stop() {
+            self.interrupted.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    /// Fails the search if it was interrupted, unless [ffi::SearchInput::partial_results] is
+    /// set, then the returned warning says the results are partial.
+    pub fn check(
+        &self,
+        input: &ffi::SearchInput,
+        index_path: &std::path::PathBuf,
+    ) -> Result<Option<String>, std::io::Error> {
+        if !self.interrupted.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let reason = if self.is_cancelled() {
+            "was cancelled".to_string()
+        } else {
+            format!("timed out after {} ms", input.timeout_ms)
+        };
+        if input.partial_results {
+            Ok(Some(format!("Search {}, the results are partial", reason)))
+        } else {
+            Err(Error::other(format!(
+                "Search of {:?} text search index {}",
+                index_path, reason
+            )))
+        }
+    }
+}
+
+/// Runs the search, interruptible only if there is something to interrupt it (the interruptible
+/// one can't skip documents as e.g. TopDocs does).
+pub fn search<C: Collector>(
+    searcher: &Searcher,
+    query: &dyn Query,
+    collector: &C,
+    interruption: &Interruption,
+) -> tantivy::Result<C::Fruit> {
+    if interruption.is_active() {
+        searcher.search(
+            query,
+            &InterruptibleCollector {
+                inner: collector,
+                interruption,
+            },
+        )
+    } else {
+        searcher.search(query, collector)
+    }
+}
+
+/// Regex query which stops enumerating the matching terms once the search is interrupted (a
+/// broad pattern can go through most of the term dictionary before any document is collected).
+#[derive(Clone, Debug)]
+pub struct InterruptibleRegexQuery {
+    regex: Arc<Regex>,
+    field: Field,
+    interruption: Interruption,
+}
+
+impl InterruptibleRegexQuery {
+    pub fn from_pattern(
+        pattern: &str,
+        field: Field,
+        interruption: &Interruption,
+    ) -> Result<InterruptibleRegexQuery, String> {
+        match Regex::new(pattern) {
+            Ok(regex) => Ok(InterruptibleRegexQuery {
+                regex: Arc::new(regex),
+                field,
+                interruption: interruption.clone(),
+            }),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+impl Query for InterruptibleRegexQuery {
+    fn weight(&self, _enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        let automaton = InterruptibleAutomaton {
+            inner: self.regex.clone(),
+            interruption: self.interruption.clone(),
+            steps: AtomicU32::new(0),
+        };
+        Ok(Box::new(AutomatonWeight::new(self.field, automaton)))
+    }
+}
+
+/// Stops the term dictionary stream once the search is interrupted (the remaining terms are
+/// treated as not matching).
+struct InterruptibleAutomaton<A> {
+    inner: Arc<A>,
+    interruption: Interruption,
+    /// Number of explored states, the interruption is checked only every CHECK_INTERVAL.
+    steps: AtomicU32,
+}
+
+impl<A: Automaton> Automaton for InterruptibleAutomaton<A> {
+    type State = A::State;
+
+    fn start(&self) -> A::State {
+        self.inner.start()
+    }
+
+    fn is_match(&self, state: &A::State) -> bool {
+        self.inner.is_match(state)
+    }
+
+    fn can_match(&self, state: &A::State) -> bool {
+        if self.interruption.interrupted.load(Ordering::Relaxed) {
+            return false;
+        }
+        if self
+            .steps
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(CHECK_INTERVAL)
+            && self.interruption.stop()
+        {
+            return false;
+        }
+        self.inner.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &A::State) -> bool {
+        self.inner.will_always_match(state)
+    }
+
+    fn accept(&self, state: &A::State, byte: u8) -> A::State {
+        self.inner.accept(state, byte)
+    }
+}
+
+/// Stops collecting (keeping what's collected so far) once the search is interrupted.
+struct InterruptibleCollector<'a, C: Collector> {
+    inner: &'a C,
+    interruption: &'a Interruption,
+}
+
+impl<C: Collector> Collector for InterruptibleCollector<'_, C> {
+    type Fruit = C::Fruit;
+    type Child = C::Child;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<C::Child> {
+        self.inner.for_segment(segment_ord, segment_reader)
+    }
+
+    fn requires_scoring(&self) -> bool {
+        self.inner.requires_scoring()
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<<C::Child as SegmentCollector>::Fruit>,
+    ) -> tantivy::Result<C::Fruit> {
+        self.inner.merge_fruits(segment_fruits)
+    }
+
+    fn collect_segment(
+        &self,
+        weight: &dyn Weight,
+        segment_ord: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<<C::Child as SegmentCollector>::Fruit> {
+        let mut segment_collector = self.for_segment(segment_ord, segment_reader)?;
+        // NOTE: Building the scorer can take long as well (e.g. a regex goes through the term
+        // dictionary), so the interruption is checked before and after it.
+        if self.interruption.stop() {
+            return Ok(segment_collector.harvest());
+        }
+        let requires_scoring = self.requires_scoring();
+        let alive_bitset = segment_reader.alive_bitset();
+        let mut scorer = weight.scorer(segment_reader, 1.0)?;
+        if self.interruption.stop() {
+            return Ok(segment_collector.harvest());
+        }
+        // NOTE: Without scoring, documents are collected in blocks (e.g. aggregations are much
+        // faster that way).
+        let mut block: Vec<DocId> = Vec::with_capacity(COLLECT_BLOCK_BUFFER_LEN);
+        let mut since_check = 0;
+        let mut doc = scorer.doc();
+        while doc != TERMINATED {
+            since_check += 1;
+            if since_check == CHECK_INTERVAL {
+                since_check = 0;
+                if self.interruption.stop() {
+                    break;
+                }
+            }
+            if alive_bitset.is_none_or(|a| a.is_alive(doc)) {
+                if requires_scoring {
+                    segment_collector.collect(doc, scorer.score());
+                } else {
+                    block.push(doc);
+                    if block.len() == COLLECT_BLOCK_BUFFER_LEN {
+                        segment_collector.collect_block(&block);
+                        block.clear();
+                    }
+                }
+            }
+            doc = scorer.advance();
+        }
+        segment_collector.collect_block(&block);
+        Ok(segment_collector.harvest())
+    }
+}
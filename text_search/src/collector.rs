@@ -0,0 +1,280 @@
+//! Collector used by search whenever plain TopDocs is not enough: custom scoring, minimum score
+//! threshold and deduplication of hits sharing the same fast field value.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use tantivy::collector::{
+    Collector, ScoreSegmentTweaker, ScoreTweaker, SegmentCollector, TopNComputer,
+};
+use tantivy::columnar::DynamicColumn;
+use tantivy::{DocAddress, DocId, Score, Searcher, SegmentOrdinal, SegmentReader, TantivyError};
+
+use crate::scoring::{SearchScorer, SegmentSearchScorer};
+use crate::search_open_fast_field_columns;
+
+/// Value of the deduplication fast field (floats are compared by their bits).
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum DedupKey {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(u64),
+    DateTime(i64),
+    Str(String),
+}
+
+fn dedup_key(columns: &[DynamicColumn], doc: DocId) -> Option<DedupKey> {
+    for column in columns {
+        let key = match column {
+            DynamicColumn::Bool(c) => c.first(doc).map(DedupKey::Bool),
+            DynamicColumn::I64(c) => c.first(doc).map(DedupKey::I64),
+            DynamicColumn::U64(c) => c.first(doc).map(DedupKey::U64),
+            DynamicColumn::F64(c) => c.first(doc).map(|v| DedupKey::F64(v.to_bits())),
+            DynamicColumn::DateTime(c) => c
+                .first(doc)
+                .map(|v| DedupKey::DateTime(v.into_timestamp_nanos())),
+            DynamicColumn::Str(c) => c.ords().first(doc).and_then(|ord| {
+                let mut s = String::new();
+                match c.ord_to_str(ord, &mut s) {
+                    Ok(true) => Some(DedupKey::Str(s)),
+                    _ => None,
+                }
+            }),
+            DynamicColumn::IpAddr(_) | DynamicColumn::Bytes(_) => None,
+        };
+        if key.is_some() {
+            return key;
+        }
+    }
+    None
+}
+
+/// Orders hits by score (descending), equally scored ones by the document (ascending).
+fn hit_order<D: Ord>(a: &(Score, D), b: &(Score, D)) -> Ordering {
+    b.0.partial_cmp(&a.0)
+        .unwrap_or(Ordering::Equal)
+        .then(a.1.cmp(&b.1))
+}
+
+pub struct HitFruit {
+    /// Top hits sorted by score (descending).
+    pub hits: Vec<(Score, DocAddress)>,
+    /// Number of all hits above the threshold (after the deduplication), only if counted.
+    pub count: u64,
+}
+
+pub struct HitCollector {
+    limit: usize,
+    min_score: Score,
+    scorer: Option<SearchScorer>,
+    dedup_field: Option<String>,
+    count_total: bool,
+}
+
+impl HitCollector {
+    pub fn new(
+        searcher: &Searcher,
+        limit: usize,
+        min_score: Score,
+        scorer: Option<SearchScorer>,
+        dedup_field: &str,
+        count_total: bool,
+    ) -> tantivy::Result<HitCollector> {
+        let dedup_field = if dedup_field.is_empty() {
+            None
+        } else {
+            let field = match searcher.schema().find_field(dedup_field) {
+                Some((field, _)) => field,
+                None => return Err(TantivyError::FieldNotFound(dedup_field.to_string())),
+            };
+            if !searcher.schema().get_field_entry(field).is_fast() {
+                return Err(TantivyError::SchemaError(format!(
+                    "deduplication field '{}' has to be fast",
+                    dedup_field
+                )));
+            }
+            Some(dedup_field.to_string())
+        };
+        Ok(HitCollector {
+            limit,
+            min_score,
+            scorer,
+            dedup_field,
+            count_total,
+        })
+    }
+}
+
+pub struct HitSegmentFruit {
+    segment_ord: SegmentOrdinal,
+    top_n: Vec<(Score, DocId)>,
+    count: u64,
+    best_by_key: HashMap<DedupKey, (Score, DocId)>,
+    seen_keys: Option<HashSet<DedupKey>>,
+}
+
+pub struct HitSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    min_score: Score,
+    scorer: Option<SegmentSearchScorer>,
+    dedup_columns: Option<Vec<DynamicColumn>>,
+    /// Hits without the deduplication key (all hits if there is no deduplication).
+    top_n: TopNComputer<Score, DocId>,
+    count: u64,
+    limit: usize,
+    /// Best hit of each deduplication key which can still make it into the top limit ones, the
+    /// map is pruned to the top limit keys once it grows to twice the limit.
+    best_by_key: HashMap<DedupKey, (Score, DocId)>,
+    /// Worst hit kept by the last pruning, keys whose hits are not better can't make it into
+    /// the top limit ones anymore.
+    key_threshold: Option<(Score, DocId)>,
+    /// All deduplication keys, only if the hits are counted.
+    seen_keys: Option<HashSet<DedupKey>>,
+}
+
+impl HitSegmentCollector {
+    fn collect_keyed(&mut self, key: DedupKey, hit: (Score, DocId)) {
+        if let Some(seen_keys) = &mut self.seen_keys {
+            if !seen_keys.contains(&key) {
+                seen_keys.insert(key.clone());
+            }
+        }
+        match self.best_by_key.entry(key) {
+            Entry::Occupied(mut e) => {
+                if hit.0 > e.get().0 {
+                    e.insert(hit);
+                }
+            }
+            Entry::Vacant(e) => {
+                if let Some(threshold) = &self.key_threshold {
+                    if hit_order(&hit, threshold) != Ordering::Less {
+                        return;
+                    }
+                }
+                e.insert(hit);
+            }
+        }
+        if self.best_by_key.len() >= 2 * self.limit {
+            let mut hits: Vec<(Score, DocId)> = self.best_by_key.values().copied().collect();
+            let (_, threshold, _) = hits.select_nth_unstable_by(self.limit - 1, hit_order);
+            let threshold = *threshold;
+            self.best_by_key
+                .retain(|_, hit| hit_order(hit, &threshold) != Ordering::Greater);
+            self.key_threshold = Some(threshold);
+        }
+    }
+}
+
+impl Collector for HitCollector {
+    type Fruit = HitFruit;
+    type Child = HitSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<HitSegmentCollector> {
+        let scorer = match &self.scorer {
+            Some(s) => Some(s.segment_tweaker(segment_reader)?),
+            None => None,
+        };
+        let dedup_columns = match &self.dedup_field {
+            Some(f) => Some(search_open_fast_field_columns(segment_reader, f)?),
+            None => None,
+        };
+        Ok(HitSegmentCollector {
+            segment_ord,
+            min_score: self.min_score,
+            scorer,
+            dedup_columns,
+            top_n: TopNComputer::new(self.limit),
+            count: 0,
+            limit: self.limit.max(1),
+            best_by_key: HashMap::new(),
+            key_threshold: None,
+            seen_keys: if self.count_total {
+                Some(HashSet::new())
+            } else {
+                None
+            },
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, segment_fruits: Vec<HitSegmentFruit>) -> tantivy::Result<HitFruit> {
+        let mut hits: Vec<(Score, DocAddress)> = Vec::new();
+        let mut count = 0;
+        let mut best_by_key: HashMap<DedupKey, (Score, DocAddress)> = HashMap::new();
+        let mut seen_keys: HashSet<DedupKey> = HashSet::new();
+        for fruit in segment_fruits {
+            let segment_ord = fruit.segment_ord;
+            count += fruit.count;
+            hits.extend(
+                fruit
+                    .top_n
+                    .into_iter()
+                    .map(|(score, doc)| (score, DocAddress::new(segment_ord, doc))),
+            );
+            for (key, (score, doc)) in fruit.best_by_key {
+                let hit = (score, DocAddress::new(segment_ord, doc));
+                match best_by_key.entry(key) {
+                    Entry::Occupied(mut e) => {
+                        if hit.0 > e.get().0 {
+                            e.insert(hit);
+                        }
+                    }
+                    Entry::Vacant(e) => {
+                        e.insert(hit);
+                    }
+                }
+            }
+            seen_keys.extend(fruit.seen_keys.into_iter().flatten());
+        }
+        count += seen_keys.len() as u64;
+        hits.extend(best_by_key.into_values());
+        hits.sort_by(hit_order);
+        hits.truncate(self.limit);
+        Ok(HitFruit { hits, count })
+    }
+}
+
+impl SegmentCollector for HitSegmentCollector {
+    type Fruit = HitSegmentFruit;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let score = match &mut self.scorer {
+            Some(s) => s.score(doc, score),
+            None => score,
+        };
+        if score < self.min_score {
+            return;
+        }
+        if let Some(columns) = &self.dedup_columns {
+            if let Some(key) = dedup_key(columns, doc) {
+                self.collect_keyed(key, (score, doc));
+                return;
+            }
+        }
+        self.count += 1;
+        self.top_n.push(score, doc);
+    }
+
+    fn harvest(self) -> HitSegmentFruit {
+        HitSegmentFruit {
+            segment_ord: self.segment_ord,
+            top_n: self
+                .top_n
+                .into_vec()
+                .into_iter()
+                .map(|d| (d.feature, d.doc))
+                .collect(),
+            count: self.count,
+            best_by_key: self.best_by_key,
+            seen_keys: self.seen_keys,
+        }
+    }
+}
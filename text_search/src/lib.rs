@@ -1,14 +1,50 @@
+mod async_commit;
+mod auto_commit;
+mod cancellation;
+mod collector;
+mod logging;
+mod manager;
+mod metrics;
+mod mvcc;
+mod reindex;
+mod schema_diff;
+mod scoring;
+mod snapshot;
+mod transaction;
+mod wal;
+
+use async_commit::{commit_async, CommitHandle};
+use cancellation::{
+    create_cancellation_token, CancellationToken, InterruptibleRegexQuery, Interruption,
+};
 use log::debug;
+use logging::init;
+use manager::{create_index_manager, search_many, search_many_cancellable, IndexManager};
+use mvcc::{delete_document_at, purge_deleted};
+use reindex::{reindex, switch_to_reindex_target, ReindexHandle};
+use serde::Deserialize;
 use serde_json::{to_string, Value};
-use std::io::{Error, ErrorKind};
+use snapshot::{acquire_searcher, release_searcher, SearcherHandle};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{Error, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Instant;
 use tantivy::aggregation::agg_req::Aggregations;
 use tantivy::aggregation::agg_result::AggregationResults;
-use tantivy::aggregation::AggregationCollector;
-use tantivy::collector::TopDocs;
-use tantivy::directory::MmapDirectory;
-use tantivy::query::{QueryParser, RegexQuery};
+use tantivy::aggregation::{AggregationCollector, AggregationError, AggregationLimits};
+use tantivy::collector::{Count, TopDocs};
+use tantivy::columnar::DynamicColumn;
+use tantivy::directory::{Directory, MmapDirectory};
+use tantivy::json_utils::{convert_to_fast_value_and_get_term, JsonTermWriter};
+use tantivy::merge_policy::LogMergePolicy;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser};
 use tantivy::schema::*;
-use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy};
+use tantivy::{
+    DocAddress, DocId, Executor, Index, IndexReader, IndexWriter, ReloadPolicy, Score, Searcher,
+    SegmentOrdinal, SegmentReader, TantivyError,
+};
+use transaction::{begin_transaction, commit_transaction, rollback_transaction, Transaction};
 
 // NOTE: Result<T> == Result<T,std::io::Error>.
 #[cxx::bridge(namespace = "mgcxx::text_search")]
@@ -28,25 +64,71 @@ mod ffi {
     ///         "indexed": {{true|false}},
     ///         "stored": {{true|false}},
     ///         "text": {{true|false}},
+    ///         "tokenizer": "{{default|en_stem|raw|whitespace}}"
     ///       }
     ///     }
     ///   }
     /// NOTE: "properties" is just taken to be similar with other text search engines, exact
     /// senamtics might be different.
+    /// NOTE: "indexed" is supported only by bool and u64 fields, "text" only by json and text
+    /// fields. Unknown keys are rejected.
+    /// settings format (JSON string expected, empty string means defaults):
+    ///   {
+    ///     "aggregation_limits": {
+    ///       "memory_limit": {{u64, default 500MB, bytes used by a single aggregate call}},
+    ///       "bucket_limit": {{u32, default 65000, buckets returned by a single aggregate call}}
+    ///     },
+    ///     "auto_commit": {
+    ///       "max_pending": {{u64, default 0, commit once N operations are pending, 0 means no limit}},
+    ///       "interval_ms": {{u64, default 0, commit operations pending for T ms, 0 means no limit}}
+    ///     },
+    ///     "bm25": {
+    ///       "k1": {{float, default 1.2}},
+    ///       "b": {{float, default 0.75}}
+    ///     },
+    ///     "key_field": "{{field_name or json path, e.g. metadata.gid, take a look under get_document}}",
+    ///     "mvcc": {{bool, default false, take a look under SearchInput::as_of_timestamp}},
+    ///     "wal": {
+    ///       "sync_every": {{u64, default 1, fsync after every N logged operations, 0 means never}}
+    ///     }
+    ///   }
+    /// NOTE: Settings are not persisted, pass the same settings each time the index is opened.
+    /// NOTE: bm25 applies to the term queries (boosts included), searching an index with bm25 set
+    /// fails if the query contains a phrase (e.g. "a b" or a term split into multiple tokens).
+    /// NOTE: If wal is set, operations which are not committed right away (skip_commit) are
+    /// logged into the index directory until the next commit/rollback. Opening the index replays
+    /// and commits the logged operations, e.g. after a crash.
+    /// NOTE: If auto_commit is set, operations which are not committed right away (skip_commit)
+    /// are committed (and become searchable) by a background thread once one of the limits is
+    /// reached.
     struct IndexConfig {
         mappings: String,
-        // TODO(gitbuda): Add tokenizer as an option (each field can have one).
+        settings: String,
     }
 
     struct DocumentInput {
         /// JSON encoded string with data.
         /// Mappings inside IndexConfig defines how data will be handeled.
         data: String,
+        /// Only if the index has the mvcc setting: the (commit) timestamp the document is created
+        /// at, stored under the created_ts field (unless data already contains it).
+        timestamp: u64,
     }
     // NOTE: The input struct is/should_be aligned with the schema.
     struct DocumentOutput {
         data: String, // NOTE: Here should probably be Option but it's not supported in cxx.
         score: f32,   // Relevance score from search
+        /// JSON encoded object with values of [SearchInput::fast_fields] (read from the columnar
+        /// storage). Fields without a value are omitted.
+        fast_data: String,
+        /// Name of the index the hit comes from, set only by [IndexManager::search_many] (the
+        /// path of the index if set by the search_many function).
+        index_name: String,
+    }
+
+    struct FieldBoost {
+        field: String,
+        boost: f32,
     }
 
     struct SearchInput {
@@ -55,218 +137,855 @@ mod ffi {
         return_fields: Vec<String>,
         aggregation_query: String,
         limit: usize,
+        /// If true, search also counts all matching documents (not just the top limit ones) and
+        /// returns the number under [SearchOutput::total_count]. Hits dropped by min_score or
+        /// collapsed by dedup_field are not counted.
+        count_total: bool,
+        /// Fast fields (or JSON paths under fast JSON fields, e.g. "metadata.gid") to return
+        /// under [DocumentOutput::fast_data]. Values are read from the columnar storage, which
+        /// is much cheaper than fetching return_fields from the docstore. If return_fields is
+        /// empty, the docstore is not touched at all.
+        fast_fields: Vec<String>,
+        /// Boosts applied to the terms of the given fields when the search_query is parsed.
+        field_boosts: Vec<FieldBoost>,
+        /// If true, terms of the search_query are combined with AND instead of OR.
+        conjunction_by_default: bool,
+        /// If true, malformed parts of the search_query are skipped instead of failing the whole
+        /// query, the problems are reported under [SearchOutput::warnings].
+        lenient: bool,
+        /// JSON encoded score tweak (empty string means no tweak) combining the text score with
+        /// a numeric fast field value:
+        ///   {
+        ///     "field": "{{fast_field_name}}",
+        ///     "function": "{{multiply|log1p|decay}}",
+        ///     "factor": {{float, default 1.0}},
+        ///     "origin": {{float, default 0.0, decay only}},
+        ///     "scale": {{float, default 1.0, decay only}},
+        ///     "missing": {{float, value used if the document doesn't have the field}}
+        ///   }
+        /// multiply: score * factor * value
+        /// log1p: score * ln(1 + factor * value)
+        /// decay: score * 0.5 ^ (|value - origin| / scale)
+        score_tweak: String,
+        /// Hits with a (final) score lower than min_score are dropped.
+        min_score: f32,
+        /// Fast field (or a JSON path under a fast JSON field) used to collapse hits, only the
+        /// best scored hit for each value is returned (e.g. one hit per node). Hits without the
+        /// value are not collapsed. Empty string means no deduplication.
+        /// NOTE: Deduplication can't skip documents the way plain top hits do, every matching
+        /// document is scored and its value read from the fast field. Up to 2 * limit values
+        /// are kept in memory per segment, with count_total every distinct value is (to count
+        /// them exactly).
+        dedup_field: String,
+        /// Only if the index has the mvcc setting: only document versions visible at the given
+        /// timestamp are searched (created_ts <= as_of_timestamp < deleted_ts). 0 means the
+        /// latest state (documents which are not deleted).
+        as_of_timestamp: u64,
+        /// Search time limit in milliseconds, 0 means no limit. A search which takes longer
+        /// fails (take a look under partial_results). Applies to each call, e.g. to all the
+        /// indices searched by [IndexManager::search_many] together.
+        timeout_ms: u64,
+        /// If true, a timed out or cancelled search returns what it found until then (search
+        /// functions add a warning) instead of failing.
+        partial_results: bool,
         // TODO(gitbuda): Add stuff like skip.
         // NOTE: Any primitive value here is a bit of a problem because of default value on the C++
         // side.
     }
     struct SearchOutput {
         docs: Vec<DocumentOutput>,
+        /// Number of all matching documents, set only if [SearchInput::count_total] is true.
+        total_count: u64,
+        /// Problems found while parsing the search_query in the lenient mode.
+        warnings: Vec<String>,
         // TODO(gitbuda): Add stuff like page (skip, limit).
     }
 
+    /// Zero values mean defaults.
+    #[derive(Clone)]
+    struct IndexManagerConfig {
+        /// Total memory (in bytes) the writers of all managed indices can use, creating an index
+        /// fails once the budget is exhausted. 0 means unlimited.
+        /// NOTE: If writer_memory_per_index is 0, the budget is shared by the writers: each one
+        /// gets an equal part (at least 15MB). Writers are created again with their new part
+        /// whenever an index is created, closed or dropped (the ones with pending operations at
+        /// their next commit).
+        writer_memory_budget: usize,
+        /// Memory (in bytes) reserved for each index writer, default 50MB (or an equal part of
+        /// writer_memory_budget, if set).
+        writer_memory_per_index: usize,
+        /// Maximum indexing threads of each index writer, default 1 (each thread needs at least
+        /// 15MB of the writer memory).
+        writer_threads_per_index: usize,
+        /// Size of the thread pool shared by searches of all managed indices (segments are
+        /// searched in parallel). 0 means each search runs in the calling thread.
+        search_threads: usize,
+    }
+
+    struct ReindexProgress {
+        /// Number of documents copied so far.
+        processed: u64,
+        /// Number of documents in the source index when the reindex started.
+        total: u64,
+        /// True once the background work is over (successfully or not).
+        done: bool,
+        /// Non empty if the reindex failed.
+        error: String,
+    }
+
+    // NOTE: LogLevel is defined under log_callback.hpp (cxx checks the values match).
+    #[repr(u8)]
+    enum LogLevel {
+        Error = 1,
+        Warn = 2,
+        Info = 3,
+        Debug = 4,
+        Trace = 5,
+    }
+
+    unsafe extern "C++" {
+        include!("tantivy_text_search/log_callback.hpp");
+        type LogLevel;
+        type LogCallback;
+        fn log(self: &LogCallback, level: LogLevel, target: &CxxString, message: &CxxString);
+    }
+
     // NOTE: Since return type is Result<T>, always return Result<Something>.
     extern "Rust" {
         type TantivyContext;
-        fn init(_log_level: &String) -> Result<()>;
+        /// Initializes logging, call it once per process, early. It's safe to call it again to
+        /// change the log level or the callback.
+        /// log_level is one of off|error|warn|info|debug|trace, if empty, the RUST_LOG
+        /// environment variable is used (warn by default).
+        /// log_callback receives all logs (take a look under log_callback.hpp), if null, logs are
+        /// written to stderr.
+        fn init(log_level: &str, log_callback: UniquePtr<LogCallback>) -> Result<()>;
         /// path is just passed into std::path::Path::new -> pass any absolute or relative path to
         /// yours process working directory
         /// config contains mappings definition, take a look under [IndexConfig]
         fn create_index(path: &String, config: &IndexConfig) -> Result<Context>;
+        /// Checks mappings (same format as [IndexConfig::mappings]) without creating an index,
+        /// the error lists all the problems (unknown keys, wrong types, unsupported options).
+        fn validate_mappings(mappings: &str) -> Result<()>;
+        /// Returns the mappings of the index (in the [IndexConfig::mappings] format) with all the
+        /// options set explicitly.
+        fn get_mappings(context: &mut Context) -> Result<String>;
+        /// Write functions return opstamps (increasing operation sequence numbers). The opstamp
+        /// of add/delete identifies the operation itself (even if it's committed right away),
+        /// commit returns the opstamp of the commit which covers all operations with smaller
+        /// opstamps. rollback and delete_all_documents return the opstamp of the last commit, all
+        /// pending operations (greater opstamps) are discarded.
+        /// NOTE: After rollback and delete_all_documents, opstamps continue from the last commit
+        /// -> the discarded opstamps are reused.
         fn add_document(
             context: &mut Context,
             input: &DocumentInput,
             skip_commit: bool,
-        ) -> Result<()>;
+        ) -> Result<u64>;
+        /// NOTE: Not allowed with the mvcc setting, use delete_document_at instead.
         fn delete_document(
             context: &mut Context,
             input: &SearchInput,
             skip_commit: bool,
-        ) -> Result<()>;
-        fn commit(context: &mut Context) -> Result<()>;
-        fn rollback(context: &mut Context) -> Result<()>;
+        ) -> Result<u64>;
+        /// Deletes all documents (including the pending ones), the index stays usable with the
+        /// same mappings. Until the commit, rollback brings the documents back.
+        fn delete_all_documents(context: &mut Context, skip_commit: bool) -> Result<u64>;
+        /// Only if the index has the mvcc setting: marks the (not deleted) documents matching
+        /// the query as deleted at the given timestamp (the deleted_ts field) instead of removing
+        /// them, so searches as of an older timestamp still find them. The marked versions are
+        /// copies of the stored fields -> the mvcc setting requires all indexed and fast fields
+        /// to be stored.
+        /// NOTE: The pending (uncommitted) documents are marked too, a document already marked
+        /// by a pending delete isn't marked again.
+        fn delete_document_at(
+            context: &mut Context,
+            input: &SearchInput,
+            timestamp: u64,
+            skip_commit: bool,
+        ) -> Result<u64>;
+        /// Only if the index has the mvcc setting: removes the documents deleted at or before the
+        /// given timestamp (e.g. the start timestamp of the oldest active transaction).
+        fn purge_deleted(context: &mut Context, timestamp: u64, skip_commit: bool) -> Result<u64>;
+        fn commit(context: &mut Context) -> Result<u64>;
+        fn rollback(context: &mut Context) -> Result<u64>;
         fn search(context: &mut Context, input: &SearchInput) -> Result<SearchOutput>;
         fn regex_search(context: &mut Context, input: &SearchInput) -> Result<SearchOutput>;
         fn aggregate(context: &mut Context, input: &SearchInput) -> Result<DocumentOutput>;
+        /// Returns the number of documents matching the search query without fetching any of
+        /// them (search_fields and search_query are the only relevant inputs).
+        fn count(context: &mut Context, input: &SearchInput) -> Result<u64>;
+        /// With the mvcc setting only the alive (not deleted) versions are counted.
         fn get_num_docs(context: &mut Context) -> Result<u64>;
+        /// Returns all stored fields (as a JSON object under data) of the document with the given
+        /// value of the key_field (take a look under [IndexConfig::settings]). If there is no
+        /// such document, data is an empty string. If there are more, the first one is returned.
+        /// The key is converted to the type of the key field (an error if it doesn't fit) and
+        /// matched exactly, so the key field has to be an indexed u64, bool, raw tokenized text
+        /// or json field. String values of a json key field are matched only if it uses the raw
+        /// tokenizer. With the mvcc setting only the alive (not deleted) versions are returned.
+        fn get_document(context: &mut Context, key: &str) -> Result<DocumentOutput>;
+        /// Same as get_document, documents are returned in the order of keys.
+        fn get_documents(context: &mut Context, keys: Vec<String>) -> Result<Vec<DocumentOutput>>;
+
+        /// Interrupts the searches it's passed to once cancelled (e.g. from another thread when
+        /// the query is aborted), searches started after the cancellation are interrupted right
+        /// away. The interrupted search behaves as a timed out one (take a look under
+        /// [SearchInput::timeout_ms]).
+        type CancellationToken;
+        fn create_cancellation_token() -> Box<CancellationToken>;
+        fn cancel(self: &CancellationToken);
+        fn is_cancelled(self: &CancellationToken) -> bool;
+        /// Same as the functions without the _cancellable suffix (there are such variants of the
+        /// SearcherHandle and Transaction searches as well).
+        fn search_cancellable(
+            context: &mut Context,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<SearchOutput>;
+        fn regex_search_cancellable(
+            context: &mut Context,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<SearchOutput>;
+        fn aggregate_cancellable(
+            context: &mut Context,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<DocumentOutput>;
+        /// NOTE: With partial_results, the count of the interrupted search is partial (there is
+        /// no warning).
+        fn count_cancellable(
+            context: &mut Context,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<u64>;
+
+        /// Handle of a commit running in the background.
+        type CommitHandle;
+        /// Same as commit but the commit runs in the background. Write operations (including
+        /// commits) called after it wait until it's done, searches see the committed changes
+        /// once it's done.
+        fn commit_async(context: &mut Context) -> Result<Box<CommitHandle>>;
+        fn is_done(self: &CommitHandle) -> bool;
+        /// Waits for the commit to complete and returns its opstamp (or its error).
+        fn wait(self: &CommitHandle) -> Result<u64>;
+        /// Error of the failed commit, empty string while it's running or if it succeeded.
+        fn error(self: &CommitHandle) -> String;
+
+        /// Searcher pinned to the index state (commit) at the time it was acquired, e.g. to get
+        /// repeatable reads across multiple searches. Later commits are not visible through it.
+        /// NOTE: The pinned segments are kept (even if merged away) until the handle is released
+        /// -> don't keep it around longer than needed.
+        type SearcherHandle;
+        fn acquire_searcher(context: &mut Context) -> Result<Box<SearcherHandle>>;
+        /// Destroying the handle releases it as well.
+        fn release_searcher(handle: Box<SearcherHandle>);
+        /// Identifies the pinned index state, handles acquired between the same two commits have
+        /// the same generation.
+        fn generation(self: &SearcherHandle) -> u64;
+        /// Same as the functions with the same name but against the pinned index state.
+        fn search(self: &SearcherHandle, input: &SearchInput) -> Result<SearchOutput>;
+        fn regex_search(self: &SearcherHandle, input: &SearchInput) -> Result<SearchOutput>;
+        fn aggregate(self: &SearcherHandle, input: &SearchInput) -> Result<DocumentOutput>;
+        fn count(self: &SearcherHandle, input: &SearchInput) -> Result<u64>;
+        fn search_cancellable(
+            self: &SearcherHandle,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<SearchOutput>;
+        fn regex_search_cancellable(
+            self: &SearcherHandle,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<SearchOutput>;
+        fn aggregate_cancellable(
+            self: &SearcherHandle,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<DocumentOutput>;
+        fn count_cancellable(
+            self: &SearcherHandle,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<u64>;
+        fn get_num_docs(self: &SearcherHandle) -> Result<u64>;
+
+        /// Pending changes of a single transaction, visible only through the transaction until
+        /// they are committed (replayed into the index) or rolled back. Searches through the
+        /// transaction see the latest commit of the index without the pending deletes plus the
+        /// pending adds (kept in a small in-memory index).
+        /// NOTE: Scores of the pending adds are computed with the statistics of the in-memory
+        /// index, so they are only roughly comparable with the scores of committed documents.
+        type Transaction;
+        fn begin_transaction(context: &mut Context) -> Result<Box<Transaction>>;
+        /// Replays all the changes into the index (in the order they were made) and returns
+        /// the opstamp of the commit (or of the last commit if skip_commit is true).
+        /// NOTE: If it fails, some of the changes might be pending in the index -> rollback.
+        fn commit_transaction(
+            transaction: Box<Transaction>,
+            context: &mut Context,
+            skip_commit: bool,
+        ) -> Result<u64>;
+        /// Discards all the changes, destroying the transaction does the same.
+        fn rollback_transaction(transaction: Box<Transaction>);
+        fn add_document(self: &mut Transaction, input: &DocumentInput) -> Result<()>;
+        fn delete_document(self: &mut Transaction, input: &SearchInput) -> Result<()>;
+        fn delete_document_at(
+            self: &mut Transaction,
+            input: &SearchInput,
+            timestamp: u64,
+        ) -> Result<()>;
+        fn search(self: &mut Transaction, input: &SearchInput) -> Result<SearchOutput>;
+        fn count(self: &mut Transaction, input: &SearchInput) -> Result<u64>;
+        fn search_cancellable(
+            self: &mut Transaction,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<SearchOutput>;
+        fn count_cancellable(
+            self: &mut Transaction,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<u64>;
+
+        /// Returns JSON encoded process wide metrics (all indices together):
+        ///   {
+        ///     "searches_total", "search_errors_total": {{search|regex_search|count|aggregate calls}},
+        ///     "search_latency": {{histogram}},
+        ///     "documents_added_total",
+        ///     "delete_queries_total": {{delete queries, not deleted documents (take a look
+        ///       under get_index_metrics num_deleted_docs)}},
+        ///     "commits_total", "commit_errors_total", "rollbacks_total",
+        ///     "commit_duration", "reload_duration": {{histogram}},
+        ///     "merges_scheduled_total", "merge_scheduled_segments_total": {{merges scheduled by
+        ///       the merge policy and their segments, not the finished ones}}
+        ///   }
+        /// histogram format (durations in microseconds, buckets are cumulative, the last "le" is
+        /// null, meaning +Inf):
+        ///   {"count": u64, "sum_us": u64, "buckets": [{"le": u64, "count": u64}, ...]}
+        fn get_metrics() -> Result<String>;
+        /// Returns JSON encoded health of the given index (as seen by the last commit):
+        ///   {"num_docs", "num_deleted_docs", "num_segments", "size_bytes"}
+        fn get_index_metrics(context: &mut Context) -> Result<String>;
+        /// Waits for the merging threads, commits (if commit_changes is true) or rolls back the
+        /// pending changes and releases the directory lock. The data stays on disk, so the index
+        /// can be opened again with create_index.
+        fn close_index(context: Context, commit_changes: bool) -> Result<()>;
+        /// Adds the fields which are in mappings (same format as [IndexConfig::mappings]) but not
+        /// in the index, existing documents are treated as missing them. Any other difference
+        /// (removed or changed fields) is rejected with an error listing all the differences.
+        /// NOTE: Pending changes are committed first.
+        /// NOTE: Pass the new mappings to create_index when the index is opened next time.
+        /// NOTE: The index metadata is replaced atomically, if the alter fails (or the process
+        /// crashes) the index stays as it was and can be opened with the old mappings.
+        /// NOTE: If the index can't be opened again after the change, all the following writes
+        /// fail and the index has to be opened again with create_index.
+        fn alter_index(context: &mut Context, mappings: &str) -> Result<()>;
+
+        /// Handle of a reindex running in the background, destroying an unfinished one cancels
+        /// it (the target index stays on disk).
+        type ReindexHandle;
+        /// Creates a new index at target_path (config describes it) and copies all documents of
+        /// the source index into it in the background. Only stored fields can be copied -> an
+        /// indexed or fast field which isn't stored is an error unless the transform removes it.
+        /// Changes made to the source after the call are not copied. If the source has the mvcc
+        /// setting, only the versions which are not deleted are copied.
+        /// transform (JSON encoded, empty string means no transform) is applied to each
+        /// document before it's added (fields which are not in the new mappings are ignored):
+        ///   {
+        ///     "rename": {"{{old_field_name}}": "{{new_field_name}}", ...},
+        ///     "remove": ["{{field_name}}", ...]
+        ///   }
+        fn reindex(
+            source: &mut Context,
+            target_path: &String,
+            config: &IndexConfig,
+            transform: &str,
+        ) -> Result<Box<ReindexHandle>>;
+        fn progress(self: &ReindexHandle) -> ReindexProgress;
+        /// Waits for the reindex to complete and switches source to the target index, the old
+        /// one is returned (drop or close it). If the reindex failed, source is untouched.
+        /// NOTE: The switch is atomic only in memory (for the users of source), nothing is
+        /// renamed on disk -> the index now lives at target_path, pass it to create_index when
+        /// the index is opened next time. Recording which path is current (e.g. in the catalog
+        /// of the host) is up to the caller, both indices stay on disk until the old one is
+        /// dropped.
+        fn switch_to_reindex_target(
+            handle: Box<ReindexHandle>,
+            source: &mut Context,
+        ) -> Result<Context>;
         fn drop_index(context: Context) -> Result<()>;
+
+        /// Owns named indices and shares writer memory budget and search threads across them.
+        /// Only one index per directory is allowed (across all index managers).
+        type IndexManager;
+        fn create_index_manager(config: &IndexManagerConfig) -> Result<Box<IndexManager>>;
+        /// Creates (or opens the existing) index at path and registers it under name.
+        fn create_index(
+            self: &mut IndexManager,
+            name: &String,
+            path: &String,
+            config: &IndexConfig,
+        ) -> Result<()>;
+        /// The returned context is valid until the index is closed/dropped or the manager is
+        /// destroyed, use it with all the functions above. The ones taking over or replacing
+        /// the context fail: drop_index and close_index (use the IndexManager methods instead)
+        /// and switch_to_reindex_target (not supported for managed indices).
+        /// NOTE: unsafe only because cxx requires it to expose the explicit lifetime.
+        unsafe fn get_index<'a>(
+            self: &'a mut IndexManager,
+            name: &String,
+        ) -> Result<&'a mut Context>;
+        /// Names of all managed indices (sorted).
+        fn list_indexes(self: &IndexManager) -> Vec<String>;
+        /// Unregisters the index and closes it (take a look under the close_index function).
+        fn close_index(self: &mut IndexManager, name: &String, commit_changes: bool) -> Result<()>;
+        /// Unregisters the index and removes the data.
+        fn drop_index(self: &mut IndexManager, name: &String) -> Result<()>;
+        /// Runs the same search against all given indices (all managed indices if index_names
+        /// is empty) and merges the hits by score into a single output, each hit is tagged with
+        /// its [DocumentOutput::index_name]. The limit applies to the merged hits, total_count
+        /// is the sum and warnings are prefixed with the index name.
+        /// Duplicated names are searched once.
+        /// NOTE: Scores are computed with per index statistics (e.g. term frequencies), so they
+        /// are only roughly comparable across indices.
+        /// NOTE: min_score and dedup_field apply to each index separately, hits of different
+        /// indices sharing the dedup_field value are not collapsed.
+        fn search_many(
+            self: &mut IndexManager,
+            index_names: Vec<String>,
+            input: &SearchInput,
+        ) -> Result<SearchOutput>;
+        /// Same as [IndexManager::search_many] but for indices which are not managed, hits are
+        /// tagged with the index paths.
+        fn search_many(contexts: &[Context], input: &SearchInput) -> Result<SearchOutput>;
+        /// Same as the search_many functions, the timeout and the token apply to the searches
+        /// of all the indices together.
+        fn search_many_cancellable(
+            self: &mut IndexManager,
+            index_names: Vec<String>,
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<SearchOutput>;
+        fn search_many_cancellable(
+            contexts: &[Context],
+            input: &SearchInput,
+            token: &CancellationToken,
+        ) -> Result<SearchOutput>;
     }
 }
 
 impl ffi::SearchInput {
     fn effective_limit(&self) -> usize {
-        if self.limit == 0 { 1000 } else { self.limit }
+        if self.limit == 0 {
+            1000
+        } else {
+            self.limit
+        }
     }
 }
 
+/// Missing limits are the tantivy defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AggregationLimitsSettings {
+    #[serde(default)]
+    memory_limit: Option<u64>,
+    #[serde(default)]
+    bucket_limit: Option<u32>,
+}
+
+/// Parsed [ffi::IndexConfig::settings].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexSettings {
+    /// Protect the process from aggregations over high-cardinality fields, exceeding them fails
+    /// the aggregation.
+    #[serde(default)]
+    aggregation_limits: AggregationLimitsSettings,
+    /// If set, pending operations are committed in the background (take a look under
+    /// auto_commit).
+    #[serde(default)]
+    auto_commit: Option<auto_commit::AutoCommitSettings>,
+    /// If set, search results are scored by BM25 with the given parameters (computed over all
+    /// terms of the query, phrases are rejected) instead of the tantivy defaults.
+    #[serde(default)]
+    bm25: Option<scoring::Bm25Settings>,
+    /// Field (or a JSON path under a JSON field) uniquely identifying documents, used by
+    /// get_document(s).
+    #[serde(default)]
+    key_field: Option<String>,
+    /// Maintains the created_ts and deleted_ts fields (take a look under mvcc).
+    #[serde(default)]
+    mvcc: bool,
+    /// If set, uncommitted operations are logged (take a look under wal).
+    #[serde(default)]
+    wal: Option<wal::WalSettings>,
+}
+
 pub struct TantivyContext {
     pub index_path: std::path::PathBuf,
     pub schema: Schema,
+    pub settings: IndexSettings,
     pub index: Index,
-    pub index_writer: IndexWriter,
     pub index_reader: IndexReader,
+    resources: IndexResources,
+    /// Shared with the auto-commit thread.
+    writer: Arc<SharedIndexWriter>,
+    /// Stops the auto-commit thread once dropped.
+    auto_commit: Option<auto_commit::AutoCommit>,
+    /// Threads of the commits started by commit_async (the finished ones are removed lazily).
+    async_commits: Vec<std::thread::JoinHandle<()>>,
+    /// Set while the context is owned by [manager::IndexManager], functions taking over or
+    /// replacing the context (close_index, drop_index and switch_to_reindex_target) reject it.
+    managed: bool,
 }
 
-fn init(_log_level: &String) -> Result<(), std::io::Error> {
-    // TODO(gitbuda): Used as a library code inside a C++ application -> align logger format.
-    let log_init_res = env_logger::try_init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "warn"),
-    );
-    // TODO(gitbuda): If more than one module tries to do this -> the later call might fail ->
-    // in that case, this code should be adjusted (or the error should be ignored because the
-    // logger is already initialized) -> if this happens consider what would be the best solution.
-    if let Err(e) = log_init_res {
-        return Err(Error::new(
-                ErrorKind::Other,
-                format!("Unable to initialize tantivy (text search engine) logger -> {} -> you should probably stop your entire process and make sure it can be initialized properly.", e),
-        ));
+/// Index writer together with the operations which are not committed yet.
+struct IndexWriterState {
+    index_writer: IndexWriter,
+    wal: Option<wal::Wal>,
+    /// Number of operations made since the last commit or rollback.
+    pending_operations: u64,
+    /// When the first of them was made.
+    pending_since: Option<Instant>,
+    auto_commit_stopped: bool,
+    /// Why the writer can't be used anymore (e.g. the index couldn't be opened again after
+    /// alter_index), every operation fails then.
+    unusable: Option<String>,
+    /// Resources the writer is created again with at the next commit (the writer of a managed
+    /// index can't be replaced while operations are pending).
+    resize: Option<IndexResources>,
+    /// Document versions changed since the last commit or rollback (take a look under mvcc).
+    mvcc_pending: mvcc::PendingVersions,
+}
+
+impl IndexWriterState {
+    fn clear_pending(&mut self) {
+        self.pending_operations = 0;
+        self.pending_since = None;
+        self.mvcc_pending = mvcc::PendingVersions::default();
+    }
+}
+
+struct SharedIndexWriter {
+    state: Mutex<IndexWriterState>,
+    /// Wakes up the auto-commit thread.
+    pending_changed: Condvar,
+}
+
+impl SharedIndexWriter {
+    fn lock(
+        &self,
+        index_path: &std::path::Path,
+    ) -> Result<MutexGuard<'_, IndexWriterState>, std::io::Error> {
+        match self.state.lock() {
+            Ok(state) => match &state.unusable {
+                Some(reason) => Err(Error::other(format!(
+                    "Writer of {:?} text search index is unusable because {}",
+                    index_path, reason
+                ))),
+                None => Ok(state),
+            },
+            Err(_) => Err(Error::other(format!(
+                "Writer of {:?} text search index is unusable because an operation on it panicked",
+                index_path
+            ))),
+        }
+    }
+}
+
+/// Returns the writer of a closed index (the background threads have to be stopped already).
+fn take_index_writer(
+    writer: Arc<SharedIndexWriter>,
+    index_path: &std::path::Path,
+) -> Result<IndexWriter, std::io::Error> {
+    match Arc::try_unwrap(writer)
+        .ok()
+        .and_then(|w| w.state.into_inner().ok())
+    {
+        Some(state) => Ok(state.index_writer),
+        None => Err(Error::other(format!(
+            "Writer of {:?} text search index is still in use",
+            index_path
+        ))),
+    }
+}
+
+const MAPPINGS_FIELD_KEYS: [&str; 6] = ["type", "fast", "indexed", "stored", "text", "tokenizer"];
+/// Tokenizers registered by tantivy by default.
+const MAPPINGS_TOKENIZERS: [&str; 4] = ["default", "en_stem", "raw", "whitespace"];
+
+fn mappings_text_indexing(tokenizer: &str) -> TextFieldIndexing {
+    TextFieldIndexing::default()
+        .set_tokenizer(tokenizer)
+        .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+}
+
+/// Reads an optional bool option of a field (false if missing).
+fn mappings_field_flag(
+    field_name: &str,
+    field: &serde_json::Map<String, Value>,
+    key: &str,
+    problems: &mut Vec<String>,
+) -> bool {
+    match field.get(key) {
+        Some(r) => match r.as_bool() {
+            Some(s) => s,
+            None => {
+                problems.push(format!("field '{}' -> {} should be bool", field_name, key));
+                false
+            }
+        },
+        None => false,
     }
-    Ok(())
 }
 
 // TODO(gitbuda): Implement full range of extract_schema options.
+/// Validates the whole mappings first and reports all the problems in a single error.
 fn create_index_schema(
     mappings: &serde_json::Map<String, Value>,
 ) -> Result<Schema, std::io::Error> {
+    let mut problems: Vec<String> = Vec::new();
+    for key in mappings.keys() {
+        if key != "properties" {
+            problems.push(format!("unknown key '{}'", key));
+        }
+    }
+    let properties_map = match mappings.get("properties").and_then(|p| p.as_object()) {
+        Some(p) => p,
+        None => {
+            problems.push("mappings has to contain properties".to_string());
+            return Err(Error::other(format!(
+                "Invalid mappings -> {}",
+                problems.join("; ")
+            )));
+        }
+    };
     let mut schema_builder = Schema::builder();
-    if let Some(properties) = mappings.get("properties") {
-        if let Some(properties_map) = properties.as_object() {
-            for (field_name, value) in properties_map {
-                let field_type = match value.get("type") {
-                    Some(r) => match r.as_str() {
-                        Some(s) => s,
-                        None => {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "field type should be a string",
-                            ));
-                        }
-                    },
-                    None => {
-                        return Err(Error::new(ErrorKind::Other, "field should have a type"));
-                    }
-                };
-                let is_stored = match value.get("stored") {
-                    Some(r) => match r.as_bool() {
-                        Some(s) => s,
-                        None => {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "field -> stored should be bool",
-                            ));
-                        }
-                    },
-                    None => false,
-                };
-                let is_fast = match value.get("fast") {
-                    Some(r) => match r.as_bool() {
-                        Some(s) => s,
-                        None => {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "field -> fast should be bool",
-                            ));
-                        }
-                    },
-                    None => false,
-                };
-                let is_text = match value.get("text") {
-                    Some(r) => match r.as_bool() {
-                        Some(s) => s,
-                        None => {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "field -> text should be bool",
-                            ));
-                        }
-                    },
-                    None => false,
-                };
-                let is_indexed = match value.get("indexed") {
-                    Some(r) => match r.as_bool() {
-                        Some(s) => s,
-                        None => {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "field -> indexed should be bool",
-                            ));
-                        }
-                    },
-                    None => false,
-                };
-                match field_type {
-                    "u64" => {
-                        let mut options = NumericOptions::default();
-                        if is_stored {
-                            options = options.set_stored();
-                        }
-                        if is_fast {
-                            options = options.set_fast();
-                        }
-                        if is_indexed {
-                            options = options.set_indexed();
-                        }
-                        schema_builder.add_u64_field(field_name, options);
-                    }
-                    "text" => {
-                        let mut options = TextOptions::default();
-                        if is_stored {
-                            options = options.set_stored();
-                        }
-                        if is_fast {
-                            options = options.set_fast(None);
-                        }
-                        if is_text {
-                            options = options | TEXT
-                        }
-                        schema_builder.add_text_field(field_name, options);
-                    }
-                    "json" => {
-                        let mut options = JsonObjectOptions::default();
-                        if is_stored {
-                            options = options.set_stored();
-                        }
-                        if is_fast {
-                            options = options.set_fast(None);
-                        }
-                        if is_text {
-                            options = options | TEXT
-                        }
-                        schema_builder.add_json_field(field_name, options);
-                    }
-                    "bool" => {
-                        let mut options = NumericOptions::default();
-                        if is_stored {
-                            options = options.set_stored();
-                        }
-                        if is_fast {
-                            options = options.set_fast();
-                        }
-                        if is_indexed {
-                            options = options.set_indexed();
-                        }
-                        schema_builder.add_bool_field(field_name, options);
+    for (field_name, value) in properties_map {
+        let field = match value.as_object() {
+            Some(f) => f,
+            None => {
+                problems.push(format!("field '{}' -> should be an object", field_name));
+                continue;
+            }
+        };
+        for key in field.keys() {
+            if !MAPPINGS_FIELD_KEYS.contains(&key.as_str()) {
+                problems.push(format!("field '{}' -> unknown key '{}'", field_name, key));
+            }
+        }
+        let field_type = match field.get("type") {
+            Some(r) => match r.as_str() {
+                Some(s) => s,
+                None => {
+                    problems.push(format!("field '{}' -> type should be a string", field_name));
+                    continue;
+                }
+            },
+            None => {
+                problems.push(format!(
+                    "field '{}' -> field should have a type",
+                    field_name
+                ));
+                continue;
+            }
+        };
+        let is_stored = mappings_field_flag(field_name, field, "stored", &mut problems);
+        let is_fast = mappings_field_flag(field_name, field, "fast", &mut problems);
+        let is_text = mappings_field_flag(field_name, field, "text", &mut problems);
+        let is_indexed = mappings_field_flag(field_name, field, "indexed", &mut problems);
+        let tokenizer = match field.get("tokenizer") {
+            Some(r) => match r.as_str() {
+                Some(s) if MAPPINGS_TOKENIZERS.contains(&s) => Some(s),
+                _ => {
+                    problems.push(format!(
+                        "field '{}' -> tokenizer should be one of {}",
+                        field_name,
+                        MAPPINGS_TOKENIZERS.join("|")
+                    ));
+                    None
+                }
+            },
+            None => None,
+        };
+        // NOTE: Numeric fields are indexed via "indexed", text and json ones via "text".
+        match field_type {
+            "u64" | "bool" if field.contains_key("text") => {
+                problems.push(format!(
+                    "field '{}' -> text is not supported by {} fields, use indexed",
+                    field_name, field_type
+                ));
+            }
+            "u64" | "bool" if field.contains_key("tokenizer") => {
+                problems.push(format!(
+                    "field '{}' -> tokenizer is not supported by {} fields",
+                    field_name, field_type
+                ));
+            }
+            "text" | "json" if field.contains_key("tokenizer") && !is_text => {
+                problems.push(format!(
+                    "field '{}' -> tokenizer requires text to be true",
+                    field_name
+                ));
+            }
+            "text" | "json" if field.contains_key("indexed") => {
+                problems.push(format!(
+                    "field '{}' -> indexed is not supported by {} fields, use text",
+                    field_name, field_type
+                ));
+            }
+            _ => {}
+        }
+        match field_type {
+            "u64" => {
+                let mut options = NumericOptions::default();
+                if is_stored {
+                    options = options.set_stored();
+                }
+                if is_fast {
+                    options = options.set_fast();
+                }
+                if is_indexed {
+                    options = options.set_indexed();
+                }
+                schema_builder.add_u64_field(field_name, options);
+            }
+            "text" => {
+                let mut options = TextOptions::default();
+                if is_stored {
+                    options = options.set_stored();
+                }
+                if is_fast {
+                    options = options.set_fast(None);
+                }
+                if is_text {
+                    options = options | TEXT;
+                    if let Some(tokenizer) = tokenizer {
+                        options = options.set_indexing_options(mappings_text_indexing(tokenizer));
                     }
-                    _ => {
-                        return Err(Error::new(ErrorKind::Other, "unknown field type"));
+                }
+                schema_builder.add_text_field(field_name, options);
+            }
+            "json" => {
+                let mut options = JsonObjectOptions::default();
+                if is_stored {
+                    options = options.set_stored();
+                }
+                if is_fast {
+                    options = options.set_fast(None);
+                }
+                if is_text {
+                    options = options | TEXT;
+                    if let Some(tokenizer) = tokenizer {
+                        options = options.set_indexing_options(mappings_text_indexing(tokenizer));
                     }
                 }
+                schema_builder.add_json_field(field_name, options);
+            }
+            "bool" => {
+                let mut options = NumericOptions::default();
+                if is_stored {
+                    options = options.set_stored();
+                }
+                if is_fast {
+                    options = options.set_fast();
+                }
+                if is_indexed {
+                    options = options.set_indexed();
+                }
+                schema_builder.add_bool_field(field_name, options);
+            }
+            _ => {
+                problems.push(format!(
+                    "field '{}' -> unknown field type '{}', use one of bool|json|text|u64",
+                    field_name, field_type
+                ));
             }
-        } else {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "mappings has to contain properties",
-            ));
         }
-    } else {
-        return Err(Error::new(
-            ErrorKind::Other,
-            "mappings has to contain properties",
-        ));
+    }
+    if !problems.is_empty() {
+        return Err(Error::other(format!(
+            "Invalid mappings -> {}",
+            problems.join("; ")
+        )));
     }
     let schema = schema_builder.build();
     Ok(schema)
 }
 
+/// Inverse of create_index_schema.
+fn get_mappings(context: &mut ffi::Context) -> Result<String, std::io::Error> {
+    let index_path = &context.tantivyContext.index_path;
+    let mut properties = serde_json::Map::new();
+    for (_, field_entry) in context.tantivyContext.schema.fields() {
+        // NOTE: The mvcc fields come from the settings.
+        if context.tantivyContext.settings.mvcc && mvcc::is_mvcc_field(field_entry.name()) {
+            continue;
+        }
+        let (field_type, text_indexing) = match field_entry.field_type() {
+            FieldType::U64(_) => ("u64", None),
+            FieldType::Bool(_) => ("bool", None),
+            FieldType::Str(options) => ("text", options.get_indexing_options()),
+            FieldType::JsonObject(options) => ("json", options.get_text_indexing_options()),
+            _ => {
+                return Err(Error::other(format!(
+                    "Field '{}' of {:?} text search index can't be expressed as mappings",
+                    field_entry.name(),
+                    index_path
+                )));
+            }
+        };
+        let mut field = serde_json::Map::new();
+        field.insert("type".to_string(), Value::from(field_type));
+        field.insert("stored".to_string(), Value::from(field_entry.is_stored()));
+        field.insert("fast".to_string(), Value::from(field_entry.is_fast()));
+        match field_type {
+            "u64" | "bool" => {
+                field.insert("indexed".to_string(), Value::from(field_entry.is_indexed()));
+            }
+            _ => {
+                field.insert("text".to_string(), Value::from(text_indexing.is_some()));
+                if let Some(text_indexing) = text_indexing {
+                    field.insert(
+                        "tokenizer".to_string(),
+                        Value::from(text_indexing.tokenizer()),
+                    );
+                }
+            }
+        }
+        properties.insert(field_entry.name().to_string(), Value::Object(field));
+    }
+    Ok(serde_json::json!({ "properties": properties }).to_string())
+}
+
+/// Checks the mappings the same way create_index does, without creating anything.
+fn validate_mappings(mappings: &str) -> Result<(), std::io::Error> {
+    let mappings = match serde_json::from_str::<serde_json::Map<String, Value>>(mappings) {
+        Ok(r) => r,
+        Err(e) => {
+            return Err(Error::other(format!("Unable to parse mappings -> {}", e)));
+        }
+    };
+    create_index_schema(&mappings)?;
+    Ok(())
+}
+
+fn create_index_settings(settings: &str) -> Result<IndexSettings, std::io::Error> {
+    if settings.is_empty() {
+        return Ok(IndexSettings::default());
+    }
+    match serde_json::from_str::<IndexSettings>(settings) {
+        Ok(s) => Ok(s),
+        Err(e) => Err(Error::other(format!(
+            "Unable to parse index settings -> {}",
+            e
+        ))),
+    }
+}
+
 fn create_index_dir_structure(
     path: &String,
     schema: &Schema,
@@ -278,63 +997,131 @@ fn create_index_dir_structure(
                 debug!("{:?} folder created", index_path);
             }
             Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "Failed to create {:?} text search index folder -> {}",
-                        index_path, e
-                    ),
-                ));
+                return Err(Error::other(format!(
+                    "Failed to create {:?} text search index folder -> {}",
+                    index_path, e
+                )));
             }
         }
     }
-    let mmap_directory = match MmapDirectory::open(&index_path) {
+    let mmap_directory = match MmapDirectory::open(index_path) {
         Ok(d) => d,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Failed to mmap text search index folder at {:?} -> {}",
-                    index_path, e
-                ),
-            ));
+            return Err(Error::other(format!(
+                "Failed to mmap text search index folder at {:?} -> {}",
+                index_path, e
+            )));
+        }
+    };
+    alter_index_cleanup(index_path)?;
+    let index_exists = match Index::exists(&mmap_directory) {
+        Ok(e) => e,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to initialize text search index under {:?} -> {}",
+                index_path, e
+            )));
         }
     };
-    // NOTE: If schema doesn't match, open_or_create is going to return an error.
-    let index = match Index::open_or_create(mmap_directory, schema.clone()) {
+    let index_res = if index_exists {
+        Index::open(mmap_directory)
+    } else {
+        Index::create(
+            mmap_directory,
+            schema.clone(),
+            tantivy::IndexSettings::default(),
+        )
+    };
+    let index = match index_res {
         Ok(index) => index,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to initialize text search index under {:?} -> {}",
-                    index_path, e
-                ),
-            ));
+            return Err(Error::other(format!(
+                "Unable to initialize text search index under {:?} -> {}",
+                index_path, e
+            )));
         }
     };
+    // NOTE: Fields are compared by name because fields added by alter_index are appended to the
+    // existing ones (the order of the fields in the mappings doesn't matter).
+    let changes = schema_diff::schema_changes(&index.schema(), schema);
+    if !changes.is_empty() {
+        return Err(Error::other(format!(
+            "Text search index under {:?} already exists with different mappings -> {}",
+            index_path,
+            schema_diff::describe(&changes)
+        )));
+    }
     Ok((index, index_path.to_path_buf()))
 }
 
+/// Resources used by the index writer and searches of an index, indices opened via
+/// [manager::IndexManager] share them.
+#[derive(Clone)]
+pub struct IndexResources {
+    writer_memory: usize,
+    /// 0 means the tantivy default (based on the number of CPUs and the writer memory).
+    writer_threads: usize,
+    /// If None, searches run in the calling thread.
+    search_executor: Option<Arc<Executor>>,
+}
+
 fn create_index(path: &String, config: &ffi::IndexConfig) -> Result<ffi::Context, std::io::Error> {
+    let resources = IndexResources {
+        writer_memory: 50_000_000,
+        writer_threads: 0,
+        search_executor: None,
+    };
+    open_index(path, config, &resources)
+}
+
+fn open_index(
+    path: &String,
+    config: &ffi::IndexConfig,
+    resources: &IndexResources,
+) -> Result<ffi::Context, std::io::Error> {
     let mappings = match serde_json::from_str::<serde_json::Map<String, Value>>(&config.mappings) {
         Ok(r) => r,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Unable to parse mappings for index at {} -> {}", path, e),
-            ));
+            return Err(Error::other(format!(
+                "Unable to parse mappings for index at {} -> {}",
+                path, e
+            )));
         }
     };
-    let schema = create_index_schema(&mappings)?;
-    let (index, path) = create_index_dir_structure(path, &schema)?;
-    let index_writer: IndexWriter = match index.writer(50_000_000) {
+    let settings = create_index_settings(&config.settings)?;
+    let schema = mvcc::extend_schema(create_index_schema(&mappings)?, &settings, path)?;
+    open_index_with_schema(path, &schema, settings, resources)
+}
+
+fn open_index_with_schema(
+    path: &String,
+    schema: &Schema,
+    settings: IndexSettings,
+    resources: &IndexResources,
+) -> Result<ffi::Context, std::io::Error> {
+    if let Some(key_field) = &settings.key_field {
+        check_key_field(schema, key_field, path)?;
+    }
+    if let Some(auto_commit) = &settings.auto_commit {
+        auto_commit.check(path)?;
+    }
+    let (mut index, path) = create_index_dir_structure(path, schema)?;
+    if let Some(executor) = &resources.search_executor {
+        // NOTE: Has to be set before the reader is created.
+        if let Err(e) = index.set_shared_multithread_executor(executor.clone()) {
+            return Err(Error::other(format!(
+                "Unable to set search executor for {:?} text search index -> {}",
+                path, e
+            )));
+        }
+    }
+    let index_writer: IndexWriter = match create_index_writer(&index, resources) {
         Ok(writer) => writer,
         Err(e) => {
-            return Err(Error::new(ErrorKind::Other, format!("Unable to initialize {:?} text search index writer -> {} This happened during the index creation. Make sure underlying machine is properly configured and try to execute create index again.", path, e)));
+            return Err(Error::other(format!("Unable to initialize {:?} text search index writer -> {} This happened during the index creation. Make sure underlying machine is properly configured and try to execute create index again.", path, e)));
         }
     };
-    
+
     // Create index reader with manual reload policy
     let index_reader = match index
         .reader_builder()
@@ -343,147 +1130,338 @@ fn create_index(path: &String, config: &ffi::IndexConfig) -> Result<ffi::Context
     {
         Ok(reader) => reader,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Unable to create index reader for {:?} -> {}", path, e),
-            ));
+            return Err(Error::other(format!(
+                "Unable to create index reader for {:?} -> {}",
+                path, e
+            )));
         }
     };
-    
-    Ok(ffi::Context {
+
+    let mut context = ffi::Context {
         tantivyContext: Box::new(TantivyContext {
             index_path: path,
-            schema,
+            // NOTE: The field order of the existing index might differ from the mappings one.
+            schema: index.schema(),
+            settings,
             index,
-            index_writer,
             index_reader,
+            resources: resources.clone(),
+            writer: Arc::new(SharedIndexWriter {
+                state: Mutex::new(IndexWriterState {
+                    index_writer,
+                    wal: None,
+                    pending_operations: 0,
+                    pending_since: None,
+                    auto_commit_stopped: false,
+                    unusable: None,
+                    resize: None,
+                    mvcc_pending: mvcc::PendingVersions::default(),
+                }),
+                pending_changed: Condvar::new(),
+            }),
+            auto_commit: None,
+            async_commits: Vec::new(),
+            managed: false,
         }),
-    })
+    };
+    wal::open(&mut context)?;
+    context.tantivyContext.auto_commit = auto_commit::start(&context.tantivyContext)?;
+    Ok(context)
+}
+
+fn create_index_writer(index: &Index, resources: &IndexResources) -> tantivy::Result<IndexWriter> {
+    let index_writer: IndexWriter = if resources.writer_threads == 0 {
+        index.writer(resources.writer_memory)?
+    } else {
+        index.writer_with_num_threads(resources.writer_threads, resources.writer_memory)?
+    };
+    index_writer.set_merge_policy(Box::new(metrics::MeteredMergePolicy::new(
+        LogMergePolicy::default(),
+    )));
+    Ok(index_writer)
+}
+
+/// Writer standing in for the real one while it's being replaced (it can't be used to write).
+fn create_placeholder_index_writer() -> tantivy::Result<IndexWriter> {
+    Index::create_in_ram(Schema::builder().build()).writer_with_num_threads(1, 15_000_000)
+}
+
+/// Changes the resources of the writer, right away if nothing is pending, otherwise at the next
+/// commit.
+fn set_writer_resources(
+    tantivy_context: &mut TantivyContext,
+    resources: IndexResources,
+) -> Result<(), std::io::Error> {
+    let current = &tantivy_context.resources;
+    if current.writer_memory == resources.writer_memory
+        && current.writer_threads == resources.writer_threads
+    {
+        return Ok(());
+    }
+    tantivy_context.resources = resources.clone();
+    let index_path = &tantivy_context.index_path;
+    let mut writer = tantivy_context.writer.lock(index_path)?;
+    if writer.pending_operations == 0 {
+        resize_index_writer(&mut writer, &resources, index_path)
+    } else {
+        writer.resize = Some(resources);
+        Ok(())
+    }
+}
+
+/// Creates the writer again with the given resources, nothing can be pending.
+fn resize_index_writer(
+    writer: &mut IndexWriterState,
+    resources: &IndexResources,
+    index_path: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    writer.resize = None;
+    let index = writer.index_writer.index().clone();
+    // NOTE: The old writer has to release the directory lock before the new one is created.
+    let resize_res = create_placeholder_index_writer().and_then(|placeholder_writer| {
+        std::mem::replace(&mut writer.index_writer, placeholder_writer).wait_merging_threads()?;
+        create_index_writer(&index, resources)
+    });
+    match resize_res {
+        Ok(index_writer) => {
+            writer.index_writer = index_writer;
+            Ok(())
+        }
+        Err(e) => {
+            // NOTE: Writes must not end up in the placeholder writer.
+            writer.unusable = Some(format!(
+                "it couldn't be created again with other resources -> {}",
+                e
+            ));
+            Err(Error::other(format!(
+                "Unable to create writer of {:?} text search index again -> {}",
+                index_path, e
+            )))
+        }
+    }
+}
+
+/// Either commits the operation (already made by the writer) right away or leaves it pending.
+fn finish_operation(
+    tantivy_context: &TantivyContext,
+    writer: &mut IndexWriterState,
+    opstamp: u64,
+    skip_commit: bool,
+    operation: impl FnOnce() -> wal::WalOperation,
+) -> Result<(), std::io::Error> {
+    // NOTE: There is nothing to recover if the operation is committed right away.
+    if skip_commit {
+        wal::log(writer, &tantivy_context.index_path, opstamp, operation)?;
+        auto_commit::operation_pending(tantivy_context, writer);
+    } else {
+        commit_writer(
+            writer,
+            &tantivy_context.index_reader,
+            &tantivy_context.index_path,
+        )?;
+    }
+    Ok(())
 }
 
 fn add_document(
     context: &mut ffi::Context,
     input: &ffi::DocumentInput,
     skip_commit: bool,
-) -> Result<(), std::io::Error> {
-    let index_path = &context.tantivyContext.index_path;
-    let schema = &context.tantivyContext.schema;
-    let document = match TantivyDocument::parse_json(&schema, &input.data) {
+) -> Result<u64, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    let mut writer = tantivy_context.writer.lock(&tantivy_context.index_path)?;
+    write_document(tantivy_context, &mut writer, input, skip_commit)
+}
+
+fn write_document(
+    tantivy_context: &TantivyContext,
+    writer: &mut IndexWriterState,
+    input: &ffi::DocumentInput,
+    skip_commit: bool,
+) -> Result<u64, std::io::Error> {
+    let index_path = &tantivy_context.index_path;
+    let schema = &tantivy_context.schema;
+    let mut document = match TantivyDocument::parse_json(schema, &input.data) {
         Ok(json) => json,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
+            return Err(Error::other(format!(
                     "Unable to add document into text search index {:?} because schema doesn't match -> {} Please check mappings.",
                     index_path, e
                 ),
             ));
         }
     };
-    let index_writer = &mut context.tantivyContext.index_writer;
-    match index_writer.add_document(document) {
-        Ok(_) => {
-            if skip_commit {
-                return Ok(());
-            } else {
-                commit(context)
+    mvcc::stamp_document(
+        schema,
+        &tantivy_context.settings,
+        &mut document,
+        input.timestamp,
+    );
+    let mvcc_version = tantivy_context.settings.mvcc.then(|| document.clone());
+    match writer.index_writer.add_document(document) {
+        Ok(opstamp) => {
+            metrics::inc(&metrics::METRICS.documents_added);
+            if let Some(version) = mvcc_version {
+                writer.mvcc_pending.add(version);
             }
+            finish_operation(tantivy_context, writer, opstamp, skip_commit, || {
+                wal::WalOperation::Add {
+                    data: input.data.to_string(),
+                    timestamp: input.timestamp,
+                }
+            })?;
+            Ok(opstamp)
         }
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Unable to add document -> {}", e),
-            ));
-        }
+        Err(e) => Err(Error::other(format!("Unable to add document -> {}", e))),
+    }
+}
+
+fn delete_parse_query(
+    index: &Index,
+    input: &ffi::SearchInput,
+    index_path: &std::path::PathBuf,
+) -> Result<Box<dyn Query>, std::io::Error> {
+    let (query, warnings) = search_parse_query(index, input, None, index_path)?;
+    // NOTE: Deleting by a partially parsed query could delete much more than intended.
+    if !warnings.is_empty() {
+        return Err(Error::other(format!(
+            "Unable to delete documents from {:?} text search index because the delete query is malformed -> {}",
+            index_path,
+            warnings.join("; ")
+        )));
     }
+    Ok(query)
 }
 
 fn delete_document(
     context: &mut ffi::Context,
     input: &ffi::SearchInput,
     skip_commit: bool,
-) -> Result<(), std::io::Error> {
-    let index_path = &context.tantivyContext.index_path;
-    let index = &context.tantivyContext.index;
-    let schema = &context.tantivyContext.schema;
-    let search_fields = search_get_fields(&input.search_fields, schema, index_path)?;
-    let query_parser = QueryParser::for_index(index, search_fields);
-    let query = match query_parser.parse_query(&input.search_query) {
-        Ok(q) => q,
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to create search query for {:?} text search index -> {}",
-                    index_path, e
-                ),
-            ));
-        }
-    };
-    let index_writer = &mut context.tantivyContext.index_writer;
-    match index_writer.delete_query(query) {
-        Ok(_) => {
-            if skip_commit {
-                return Ok(());
-            } else {
-                commit(context)
-            }
+) -> Result<u64, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    let index_path = &tantivy_context.index_path;
+    mvcc::physical_delete_check(&tantivy_context.settings, index_path)?;
+    let query = delete_parse_query(&tantivy_context.index, input, index_path)?;
+    let mut writer = tantivy_context.writer.lock(index_path)?;
+    match writer.index_writer.delete_query(query) {
+        Ok(opstamp) => {
+            metrics::inc(&metrics::METRICS.delete_queries);
+            finish_operation(tantivy_context, &mut writer, opstamp, skip_commit, || {
+                wal::WalOperation::Delete {
+                    query: wal::WalDeleteQuery::new(input),
+                }
+            })?;
+            Ok(opstamp)
         }
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to delete document from text search index at {:?} -> {}",
-                    index_path, e
-                ),
-            ));
+        Err(e) => Err(Error::other(format!(
+            "Unable to delete document from text search index at {:?} -> {}",
+            index_path, e
+        ))),
+    }
+}
+
+fn delete_all_documents(
+    context: &mut ffi::Context,
+    skip_commit: bool,
+) -> Result<u64, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    let index_path = &tantivy_context.index_path;
+    let mut writer = tantivy_context.writer.lock(index_path)?;
+    match writer.index_writer.delete_all_documents() {
+        Ok(opstamp) => {
+            writer.mvcc_pending.delete_all();
+            finish_operation(tantivy_context, &mut writer, opstamp, skip_commit, || {
+                wal::WalOperation::DeleteAll
+            })?;
+            Ok(opstamp)
         }
+        Err(e) => Err(Error::other(format!(
+            "Unable to delete all documents from text search index at {:?} -> {}",
+            index_path, e
+        ))),
     }
 }
 
-fn commit(context: &mut ffi::Context) -> Result<(), std::io::Error> {
-    let index_path = &context.tantivyContext.index_path;
-    match context.tantivyContext.index_writer.commit() {
-        Ok(_) => {
+fn commit(context: &mut ffi::Context) -> Result<u64, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    let index_path = &tantivy_context.index_path;
+    let mut writer = tantivy_context.writer.lock(index_path)?;
+    commit_writer(&mut writer, &tantivy_context.index_reader, index_path)
+}
+
+fn commit_writer(
+    writer: &mut IndexWriterState,
+    index_reader: &IndexReader,
+    index_path: &std::path::Path,
+) -> Result<u64, std::io::Error> {
+    let payload = wal::commit_payload(writer);
+    commit_writer_with_payload(writer, payload, index_reader, index_path)
+}
+
+fn commit_writer_with_payload(
+    writer: &mut IndexWriterState,
+    payload: Option<String>,
+    index_reader: &IndexReader,
+    index_path: &std::path::Path,
+) -> Result<u64, std::io::Error> {
+    let start = Instant::now();
+    let commit_res = writer
+        .index_writer
+        .prepare_commit()
+        .and_then(|mut prepared_commit| {
+            if let Some(payload) = payload {
+                prepared_commit.set_payload(&payload);
+            }
+            prepared_commit.commit()
+        });
+    match commit_res {
+        Ok(opstamp) => {
+            metrics::METRICS.commit_duration.observe(start.elapsed());
+            metrics::inc(&metrics::METRICS.commits);
+            writer.clear_pending();
+            // NOTE: The log has to be cleared right after the commit, otherwise the following
+            // operations would be logged under the committed generation and never replayed (take
+            // a look under wal).
+            wal::clear(writer, index_path)?;
             // Explicitly reload the index reader to see the new changes
-            if let Err(e) = context.tantivyContext.index_reader.reload() {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "Unable to reload reader after commit for text search index at {:?} -> {}",
-                        index_path, e
-                    ),
-                ));
+            let start = Instant::now();
+            if let Err(e) = index_reader.reload() {
+                return Err(Error::other(format!(
+                    "Unable to reload reader after commit for text search index at {:?} -> {}",
+                    index_path, e
+                )));
+            }
+            metrics::METRICS.reload_duration.observe(start.elapsed());
+            if let Some(resources) = writer.resize.take() {
+                resize_index_writer(writer, &resources, index_path)?;
             }
-            return Ok(());
+            Ok(opstamp)
         }
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to commit text search index at {:?} -> {}",
-                    index_path, e
-                ),
-            ));
+            metrics::inc(&metrics::METRICS.commit_errors);
+            Err(Error::other(format!(
+                "Unable to commit text search index at {:?} -> {}",
+                index_path, e
+            )))
         }
     }
 }
 
-fn rollback(context: &mut ffi::Context) -> Result<(), std::io::Error> {
-    let index_path = &context.tantivyContext.index_path;
-    match context.tantivyContext.index_writer.rollback() {
-        Ok(_) => {
-            return Ok(());
-        }
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to rollback text search index at {:?} -> {}",
-                    index_path, e
-                ),
-            ));
+fn rollback(context: &mut ffi::Context) -> Result<u64, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    let index_path = &tantivy_context.index_path;
+    let mut writer = tantivy_context.writer.lock(index_path)?;
+    match writer.index_writer.rollback() {
+        Ok(opstamp) => {
+            metrics::inc(&metrics::METRICS.rollbacks);
+            writer.clear_pending();
+            wal::clear(&mut writer, index_path)?;
+            Ok(opstamp)
         }
+        Err(e) => Err(Error::other(format!(
+            "Unable to rollback text search index at {:?} -> {}",
+            index_path, e
+        ))),
     }
 }
 
@@ -492,297 +1470,1044 @@ fn search_get_fields(
     schema: &Schema,
     index_path: &std::path::PathBuf,
 ) -> Result<Vec<Field>, std::io::Error> {
-    let mut result: Vec<Field> = Vec::new();
-    result.reserve(fields.len());
+    let mut result: Vec<Field> = Vec::with_capacity(fields.len());
     for name in fields {
         match schema.get_field(name) {
             Ok(f) => result.push(f),
             Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("{} inside {:?} text search index", e, index_path),
-                ));
+                return Err(Error::other(format!(
+                    "{} inside {:?} text search index",
+                    e, index_path
+                )));
             }
         }
     }
     Ok(result)
 }
 
-fn search(
-    context: &mut ffi::Context,
+/// Parses the search_query (taking into account field boosts, query parser options and the
+/// custom BM25 parameters, if any) and returns the query together with the lenient parsing
+/// warnings (always empty in the strict mode).
+fn search_parse_query(
+    index: &Index,
     input: &ffi::SearchInput,
-) -> Result<ffi::SearchOutput, std::io::Error> {
-    let index_path = &context.tantivyContext.index_path;
-    let index = &context.tantivyContext.index;
-    let schema = &context.tantivyContext.schema;
-    let reader = &context.tantivyContext.index_reader;
-
-    let search_fields = search_get_fields(&input.search_fields, schema, index_path)?;
-    let return_fields = search_get_fields(&input.return_fields, schema, index_path)?;
-    let query_parser = QueryParser::for_index(index, search_fields);
-    let query = match query_parser.parse_query(&input.search_query) {
-        Ok(q) => q,
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to create search query for {:?} text search index -> {}",
-                    index_path, e
-                ),
-            ));
+    bm25: Option<&scoring::Bm25Settings>,
+    index_path: &std::path::PathBuf,
+) -> Result<(Box<dyn Query>, Vec<String>), std::io::Error> {
+    let schema = index.schema();
+    let search_fields = search_get_fields(&input.search_fields, &schema, index_path)?;
+    let mut query_parser = QueryParser::for_index(index, search_fields);
+    if input.conjunction_by_default {
+        query_parser.set_conjunction_by_default();
+    }
+    let mut field_boosts = HashMap::new();
+    for field_boost in &input.field_boosts {
+        let field = match schema.get_field(&field_boost.field) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(Error::other(format!(
+                    "{} inside {:?} text search index",
+                    e, index_path
+                )));
+            }
+        };
+        field_boosts.insert(field, field_boost.boost);
+    }
+    let parsed = match bm25 {
+        // NOTE: The field boosts are applied while the term queries are replaced.
+        Some(settings) => scoring::parse_bm25_query(
+            &query_parser,
+            &schema,
+            &field_boosts,
+            settings,
+            &input.search_query,
+            input.conjunction_by_default,
+            input.lenient,
+        ),
+        None => {
+            for (field, boost) in field_boosts {
+                query_parser.set_field_boost(field, boost);
+            }
+            if input.lenient {
+                Ok(query_parser.parse_query_lenient(&input.search_query))
+            } else {
+                query_parser
+                    .parse_query(&input.search_query)
+                    .map(|q| (q, Vec::new()))
+            }
         }
     };
+    match parsed {
+        Ok((query, errors)) => Ok((query, errors.iter().map(|e| e.to_string()).collect())),
+        Err(e) => Err(Error::other(format!(
+            "Unable to create search query for {:?} text search index -> {}",
+            index_path, e
+        ))),
+    }
+}
 
-    let searcher = reader.searcher();
-    let top_docs = match searcher.search(&query, &TopDocs::with_limit(input.effective_limit())) {
-        Ok(docs) => docs,
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to perform text search under {:?} -> {}",
-                    index_path, e
-                ),
-            ));
+/// Returns the top documents and, if [ffi::SearchInput::count_total] is set, the total number of
+/// matching documents (counted in the same pass over the index). Documents matching any of the
+/// excluded queries and documents not visible as of [ffi::SearchInput::as_of_timestamp] are
+/// skipped.
+fn search_top_docs(
+    searcher: &Searcher,
+    query: &dyn Query,
+    excluded: &[Box<dyn Query>],
+    input: &ffi::SearchInput,
+    settings: &IndexSettings,
+    index_path: &std::path::PathBuf,
+    interruption: &Interruption,
+) -> Result<(Vec<(Score, DocAddress)>, u64), std::io::Error> {
+    let score_tweak = scoring::ScoreTweak::parse(&input.score_tweak)?;
+    let scorer = scoring::SearchScorer::new(searcher, score_tweak);
+    let query = mvcc::visible_query(
+        settings,
+        search_exclude(query.box_clone(), excluded),
+        input.as_of_timestamp,
+    );
+    let query = query.as_ref();
+    let search_res = match scorer {
+        // NOTE: TopDocs is faster (it skips documents that can't make it into the top ones).
+        Ok(None) if input.min_score <= 0.0 && input.dedup_field.is_empty() => {
+            let top_docs_collector = TopDocs::with_limit(input.effective_limit());
+            if input.count_total {
+                cancellation::search(searcher, query, &(top_docs_collector, Count), interruption)
+                    .map(|(top_docs, count)| (top_docs, count as u64))
+            } else {
+                cancellation::search(searcher, query, &top_docs_collector, interruption)
+                    .map(|top_docs| (top_docs, 0))
+            }
         }
+        Ok(scorer) => collector::HitCollector::new(
+            searcher,
+            input.effective_limit(),
+            input.min_score,
+            scorer,
+            &input.dedup_field,
+            input.count_total,
+        )
+        .and_then(|hit_collector| {
+            cancellation::search(searcher, query, &hit_collector, interruption)
+        })
+        .map(|fruit| {
+            let count = if input.count_total { fruit.count } else { 0 };
+            (fruit.hits, count)
+        }),
+        Err(e) => Err(e),
     };
+    match search_res {
+        Ok(r) => Ok(r),
+        Err(e) => Err(Error::other(format!(
+            "Unable to perform text search under {:?} -> {}",
+            index_path, e
+        ))),
+    }
+}
 
-    let mut docs: Vec<ffi::DocumentOutput> = Vec::with_capacity(top_docs.len());
-    for (score, doc_address) in top_docs {
-        let doc: TantivyDocument = match searcher.doc(doc_address) {
-            Ok(d) => d,
-            Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "Unable to find document inside {:?} text search index) -> {}",
-                        index_path, e
-                    ),
-                ));
+/// Wraps the query to skip all documents matching any of the excluded queries.
+fn search_exclude(query: Box<dyn Query>, excluded: &[Box<dyn Query>]) -> Box<dyn Query> {
+    if excluded.is_empty() {
+        return query;
+    }
+    let mut clauses = vec![(Occur::Must, query)];
+    clauses.extend(excluded.iter().map(|q| (Occur::MustNot, q.box_clone())));
+    Box::new(BooleanQuery::new(clauses))
+}
+
+/// Merges hits coming from multiple searches into the top limit ones (by score).
+fn search_merge_docs(docs: &mut Vec<ffi::DocumentOutput>, limit: usize) {
+    // NOTE: The sort is stable -> equally scored hits keep their order.
+    docs.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    docs.truncate(limit);
+}
+
+fn search_fast_field_value(
+    column: &DynamicColumn,
+    doc_id: DocId,
+) -> Result<Option<serde_json::Value>, std::io::Error> {
+    let value = match column {
+        DynamicColumn::Bool(c) => c.first(doc_id).map(Value::from),
+        DynamicColumn::I64(c) => c.first(doc_id).map(Value::from),
+        DynamicColumn::U64(c) => c.first(doc_id).map(Value::from),
+        DynamicColumn::F64(c) => c.first(doc_id).map(Value::from),
+        DynamicColumn::DateTime(c) => c
+            .first(doc_id)
+            .map(|d| Value::from(d.into_timestamp_micros())),
+        DynamicColumn::Str(c) => match c.ords().first(doc_id) {
+            Some(ord) => {
+                let mut s = String::new();
+                c.ord_to_str(ord, &mut s)?;
+                Some(Value::from(s))
+            }
+            None => None,
+        },
+        // NOTE: Bytes and IP columns can't be created via mappings.
+        DynamicColumn::IpAddr(_) | DynamicColumn::Bytes(_) => None,
+    };
+    Ok(value)
+}
+
+/// Opens all columns (of different types) stored under the given fast field name.
+fn search_open_fast_field_columns(
+    segment_reader: &SegmentReader,
+    name: &str,
+) -> tantivy::Result<Vec<DynamicColumn>> {
+    let handles = segment_reader.fast_fields().dynamic_column_handles(name)?;
+    let mut columns = Vec::with_capacity(handles.len());
+    for handle in handles {
+        columns.push(handle.open()?);
+    }
+    Ok(columns)
+}
+
+/// Reads [ffi::SearchInput::fast_fields] values of the given documents from the columnar storage.
+/// Columns are opened once per segment.
+fn search_retrieve_fast_fields(
+    searcher: &Searcher,
+    top_docs: &[(Score, DocAddress)],
+    input: &ffi::SearchInput,
+    index_path: &std::path::PathBuf,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>, std::io::Error> {
+    let mut result = vec![serde_json::Map::new(); top_docs.len()];
+    if input.fast_fields.is_empty() {
+        return Ok(result);
+    }
+    for name in &input.fast_fields {
+        let field = match searcher.schema().find_field(name) {
+            Some((field, _)) => field,
+            None => {
+                return Err(Error::other(format!(
+                    "The field does not exist: '{}' inside {:?} text search index",
+                    name, index_path
+                )));
             }
         };
-        let mut data: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
-        for (name, field) in input.return_fields.iter().zip(return_fields.iter()) {
-            let field_data = match doc.get_first(*field) {
-                Some(f) => f,
-                None => continue,
-            };
-            // TODO(gitbuda): Shouldn't not just be JSON -> deduce from mappings!
-            let field_as_tantivy_json = match field_data {
-                OwnedValue::Object(f) => f,
-                _ => {
-                    // TODO(gitbuda): Is error here the best?
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("Unable to convert field data to json"),
-                    ));
+        if !searcher.schema().get_field_entry(field).is_fast() {
+            return Err(Error::other(format!(
+                "fast_fields field '{}' of {:?} text search index has to be fast",
+                name, index_path
+            )));
+        }
+    }
+    // columns[segment_ord][fast_field_index] -> all columns (of different types) under that name
+    let mut columns: HashMap<SegmentOrdinal, Vec<Vec<DynamicColumn>>> = HashMap::new();
+    for ((_, doc_address), data) in top_docs.iter().zip(result.iter_mut()) {
+        let segment_ord = doc_address.segment_ord;
+        if let Entry::Vacant(entry) = columns.entry(segment_ord) {
+            let segment_reader = searcher.segment_reader(segment_ord);
+            let mut segment_columns = Vec::with_capacity(input.fast_fields.len());
+            for name in &input.fast_fields {
+                match search_open_fast_field_columns(segment_reader, name) {
+                    Ok(c) => segment_columns.push(c),
+                    Err(e) => {
+                        return Err(Error::other(format!(
+                            "Unable to read fast field '{}' inside {:?} text search index -> {}",
+                            name, index_path, e
+                        )));
+                    }
                 }
-            };
-            let field_as_json = match serde_json::to_value(field_as_tantivy_json) {
-                Ok(f) => f,
-                Err(_) => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("Unable to convert field data to json"),
-                    ));
+            }
+            entry.insert(segment_columns);
+        }
+        for (name, name_columns) in input.fast_fields.iter().zip(&columns[&segment_ord]) {
+            for column in name_columns {
+                if let Some(value) = search_fast_field_value(column, doc_address.doc_id)? {
+                    data.insert(name.to_string(), value);
+                    break;
                 }
-            };
-            data.insert(name.to_string(), field_as_json);
+            }
         }
+    }
+    Ok(result)
+}
 
-        docs.push(ffi::DocumentOutput {
-            data: match to_string(&data) {
-                Ok(s) => s,
+fn search_retrieve_docs(
+    searcher: &Searcher,
+    top_docs: Vec<(Score, DocAddress)>,
+    input: &ffi::SearchInput,
+    index_path: &std::path::PathBuf,
+) -> Result<Vec<ffi::DocumentOutput>, std::io::Error> {
+    let return_fields = search_get_fields(&input.return_fields, searcher.schema(), index_path)?;
+    let fast_data = search_retrieve_fast_fields(searcher, &top_docs, input, index_path)?;
+    let mut docs: Vec<ffi::DocumentOutput> = Vec::with_capacity(top_docs.len());
+    for ((score, doc_address), fast_data) in top_docs.into_iter().zip(fast_data) {
+        let mut data: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
+        // NOTE: Fetching a document means decompressing a whole docstore block -> skip it if
+        // nothing from the docstore is requested.
+        if !return_fields.is_empty() {
+            let doc: TantivyDocument = match searcher.doc(doc_address) {
+                Ok(d) => d,
                 Err(e) => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!(
-                            "Unable to serialize {:?} text search index data into a string -> {}",
-                            index_path, e
-                        ),
-                    ));
+                    return Err(Error::other(format!(
+                        "Unable to find document inside {:?} text search index) -> {}",
+                        index_path, e
+                    )));
                 }
-            },
-            score: score,
+            };
+            for (name, field) in input.return_fields.iter().zip(return_fields.iter()) {
+                let field_data = match doc.get_first(*field) {
+                    Some(f) => f,
+                    None => continue,
+                };
+                // TODO(gitbuda): Shouldn't not just be JSON -> deduce from mappings!
+                let field_as_tantivy_json = match field_data {
+                    OwnedValue::Object(f) => f,
+                    _ => {
+                        // TODO(gitbuda): Is error here the best?
+                        return Err(Error::other(format!(
+                            "Unable to convert field data to json. Data we have: {:?}",
+                            field_data
+                        )));
+                    }
+                };
+                let field_as_json = match serde_json::to_value(field_as_tantivy_json) {
+                    Ok(f) => f,
+                    Err(_) => {
+                        return Err(Error::other("Unable to convert field data to json"));
+                    }
+                };
+                data.insert(name.to_string(), field_as_json);
+            }
+        }
+        let (data, fast_data) = match (to_string(&data), to_string(&fast_data)) {
+            (Ok(data), Ok(fast_data)) => (data, fast_data),
+            (Err(e), _) | (_, Err(e)) => {
+                return Err(Error::other(format!(
+                    "Unable to serialize {:?} text search index data into a string -> {}",
+                    index_path, e
+                )));
+            }
+        };
+        docs.push(ffi::DocumentOutput {
+            data,
+            score,
+            fast_data,
+            index_name: String::new(),
         });
     }
-    Ok(ffi::SearchOutput { docs })
+    Ok(docs)
 }
 
-fn regex_search(
-    context: &mut ffi::Context,
+// NOTE: The searcher_* functions run against the given searcher, which is either the latest one
+// or a pinned one (take a look under snapshot).
+
+fn searcher_search(
+    searcher: &Searcher,
+    settings: &IndexSettings,
+    index_path: &std::path::PathBuf,
     input: &ffi::SearchInput,
+    interruption: &Interruption,
 ) -> Result<ffi::SearchOutput, std::io::Error> {
-    let index_path = &context.tantivyContext.index_path;
-    let schema = &context.tantivyContext.schema;
-    let reader = &context.tantivyContext.index_reader;
-    
-    let search_field = search_get_fields(&input.search_fields, schema, index_path)?[0];
-    let return_fields = search_get_fields(&input.return_fields, schema, index_path)?;
+    let (query, mut warnings) =
+        search_parse_query(searcher.index(), input, settings.bm25.as_ref(), index_path)?;
+    let (top_docs, total_count) = search_top_docs(
+        searcher,
+        &query,
+        &[],
+        input,
+        settings,
+        index_path,
+        interruption,
+    )?;
+    warnings.extend(interruption.check(input, index_path)?);
+    let docs = search_retrieve_docs(searcher, top_docs, input, index_path)?;
+    Ok(ffi::SearchOutput {
+        docs,
+        total_count,
+        warnings,
+    })
+}
 
-    let query = match RegexQuery::from_pattern(&input.search_query, search_field) {
+fn searcher_regex_search(
+    searcher: &Searcher,
+    settings: &IndexSettings,
+    index_path: &std::path::PathBuf,
+    input: &ffi::SearchInput,
+    interruption: &Interruption,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    let search_field =
+        match search_get_fields(&input.search_fields, searcher.schema(), index_path)?.first() {
+            Some(f) => *f,
+            None => {
+                return Err(Error::other(format!(
+                    "Regex search of {:?} text search index needs a search field",
+                    index_path
+                )));
+            }
+        };
+    let query = match InterruptibleRegexQuery::from_pattern(
+        &input.search_query,
+        search_field,
+        interruption,
+    ) {
         Ok(q) => q,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to create regex search query for {:?} text search index -> {}",
-                    index_path, e
-                ),
-            ));
+            return Err(Error::other(format!(
+                "Unable to create regex search query for {:?} text search index -> {}",
+                index_path, e
+            )));
         }
     };
+    let (top_docs, total_count) = search_top_docs(
+        searcher,
+        &query,
+        &[],
+        input,
+        settings,
+        index_path,
+        interruption,
+    )?;
+    let warnings = interruption.check(input, index_path)?.into_iter().collect();
+    let docs = search_retrieve_docs(searcher, top_docs, input, index_path)?;
+    Ok(ffi::SearchOutput {
+        docs,
+        total_count,
+        warnings,
+    })
+}
 
-    let searcher = reader.searcher();
-    let top_docs = match searcher.search(&query, &TopDocs::with_limit(input.effective_limit())) {
-        Ok(docs) => docs,
+/// Counts all documents matching the query without retrieving any of them.
+fn searcher_count(
+    searcher: &Searcher,
+    settings: &IndexSettings,
+    index_path: &std::path::PathBuf,
+    input: &ffi::SearchInput,
+    interruption: &Interruption,
+) -> Result<u64, std::io::Error> {
+    let (query, _) = search_parse_query(searcher.index(), input, None, index_path)?;
+    let query = mvcc::visible_query(settings, query, input.as_of_timestamp);
+    let count = match cancellation::search(searcher, query.as_ref(), &Count, interruption) {
+        Ok(c) => c as u64,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Unable to perform text search under {:?} -> {}",
-                    index_path, e
-                ),
-            ));
+            return Err(Error::other(format!(
+                "Unable to count matching documents under {:?} -> {}",
+                index_path, e
+            )));
         }
     };
-    
-    let mut docs: Vec<ffi::DocumentOutput> = Vec::with_capacity(top_docs.len());
-    for (score, doc_address) in top_docs {
-        let doc: TantivyDocument = match searcher.doc(doc_address) {
-            Ok(d) => d,
-            Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "Unable to find document inside {:?} text search index) -> {}",
-                        index_path, e
-                    ),
+    interruption.check(input, index_path)?;
+    Ok(count)
+}
+
+fn searcher_aggregate(
+    searcher: &Searcher,
+    settings: &IndexSettings,
+    index_path: &std::path::PathBuf,
+    input: &ffi::SearchInput,
+    interruption: &Interruption,
+) -> Result<ffi::DocumentOutput, std::io::Error> {
+    let (query, _) = search_parse_query(searcher.index(), input, None, index_path)?;
+    let query = mvcc::visible_query(settings, query, input.as_of_timestamp);
+    let agg_req: Aggregations = serde_json::from_str(&input.aggregation_query)?;
+    let limits = &settings.aggregation_limits;
+    let collector = AggregationCollector::from_aggs(
+        agg_req,
+        AggregationLimits::new(limits.memory_limit, limits.bucket_limit),
+    );
+    let agg_res: AggregationResults = match cancellation::search(
+        searcher,
+        query.as_ref(),
+        &collector,
+        interruption,
+    ) {
+        Ok(r) => r,
+        Err(
+            e @ TantivyError::AggregationError(
+                AggregationError::MemoryExceeded { .. }
+                | AggregationError::BucketLimitExceeded { .. },
+            ),
+        ) => {
+            return Err(Error::other(format!(
+                "Aggregation over {:?} text search index exceeded the aggregation_limits setting -> {}",
+                index_path, e
+            )));
+        }
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Failed to gather aggregation results for {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+    };
+    interruption.check(input, index_path)?;
+    let res: Value = serde_json::to_value(agg_res)?;
+    Ok(ffi::DocumentOutput {
+        data: res.to_string(),
+        score: 0.0, // Aggregation results don't have individual document scores
+        fast_data: "{}".to_string(),
+        index_name: String::new(),
+    })
+}
+
+/// Runs one of the searcher_* functions against the latest searcher.
+fn context_search<T>(
+    context: &ffi::Context,
+    input: &ffi::SearchInput,
+    token: Option<&CancellationToken>,
+    searcher_fn: impl FnOnce(
+        &Searcher,
+        &IndexSettings,
+        &std::path::PathBuf,
+        &ffi::SearchInput,
+        &Interruption,
+    ) -> Result<T, std::io::Error>,
+) -> Result<T, std::io::Error> {
+    let interruption = Interruption::new(input, token);
+    context_search_interruptible(context, input, &interruption, searcher_fn)
+}
+
+/// Same as [context_search] but the interruption can be shared by more searches (e.g. one
+/// timeout for all the indices searched by search_many).
+fn context_search_interruptible<T>(
+    context: &ffi::Context,
+    input: &ffi::SearchInput,
+    interruption: &Interruption,
+    searcher_fn: impl FnOnce(
+        &Searcher,
+        &IndexSettings,
+        &std::path::PathBuf,
+        &ffi::SearchInput,
+        &Interruption,
+    ) -> Result<T, std::io::Error>,
+) -> Result<T, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    metrics::measure_search(|| {
+        searcher_fn(
+            &tantivy_context.index_reader.searcher(),
+            &tantivy_context.settings,
+            &tantivy_context.index_path,
+            input,
+            interruption,
+        )
+    })
+}
+
+fn search(
+    context: &mut ffi::Context,
+    input: &ffi::SearchInput,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    context_search(context, input, None, searcher_search)
+}
+
+fn search_cancellable(
+    context: &mut ffi::Context,
+    input: &ffi::SearchInput,
+    token: &CancellationToken,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    context_search(context, input, Some(token), searcher_search)
+}
+
+fn regex_search(
+    context: &mut ffi::Context,
+    input: &ffi::SearchInput,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    context_search(context, input, None, searcher_regex_search)
+}
+
+fn regex_search_cancellable(
+    context: &mut ffi::Context,
+    input: &ffi::SearchInput,
+    token: &CancellationToken,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    context_search(context, input, Some(token), searcher_regex_search)
+}
+
+fn count(context: &mut ffi::Context, input: &ffi::SearchInput) -> Result<u64, std::io::Error> {
+    context_search(context, input, None, searcher_count)
+}
+
+fn count_cancellable(
+    context: &mut ffi::Context,
+    input: &ffi::SearchInput,
+    token: &CancellationToken,
+) -> Result<u64, std::io::Error> {
+    context_search(context, input, Some(token), searcher_count)
+}
+
+fn aggregate(
+    context: &mut ffi::Context,
+    input: &ffi::SearchInput,
+) -> Result<ffi::DocumentOutput, std::io::Error> {
+    context_search(context, input, None, searcher_aggregate)
+}
+
+fn aggregate_cancellable(
+    context: &mut ffi::Context,
+    input: &ffi::SearchInput,
+    token: &CancellationToken,
+) -> Result<ffi::DocumentOutput, std::io::Error> {
+    context_search(context, input, Some(token), searcher_aggregate)
+}
+
+/// Stored documents are returned by tantivy as {"field": [values]} -> single values are
+/// unwrapped to match the shape of the original document.
+fn stored_document_data(
+    schema: &Schema,
+    doc: &TantivyDocument,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut data = serde_json::Map::new();
+    for (name, mut values) in doc.to_named_doc(schema).0 {
+        let value = if values.len() == 1 {
+            serde_json::to_value(values.remove(0))
+        } else {
+            serde_json::to_value(values)
+        };
+        if let Ok(value) = value {
+            data.insert(name, value);
+        }
+    }
+    data
+}
+
+/// Key lookups match exact values -> the key field has to be indexed as a single term (numbers,
+/// booleans, raw tokenized text or JSON).
+fn check_key_field(schema: &Schema, key_field: &str, path: &str) -> Result<(), std::io::Error> {
+    let field_entry = match schema.find_field(key_field) {
+        Some((field, _)) => schema.get_field_entry(field),
+        None => {
+            return Err(Error::other(format!(
+                "Key field '{}' of text search index at {} does not exist",
+                key_field, path
+            )));
+        }
+    };
+    if !field_entry.is_indexed() {
+        return Err(Error::other(format!(
+            "Key field '{}' of text search index at {} has to be indexed",
+            key_field, path
+        )));
+    }
+    match field_entry.field_type() {
+        FieldType::U64(_) | FieldType::Bool(_) | FieldType::JsonObject(_) => Ok(()),
+        FieldType::Str(options) => match options.get_indexing_options() {
+            Some(indexing) if indexing.tokenizer() == "raw" => Ok(()),
+            _ => Err(Error::other(format!(
+                "Key field '{}' of text search index at {} has to use the raw tokenizer",
+                key_field, path
+            ))),
+        },
+        field_type => Err(Error::other(format!(
+            "Key field '{}' of text search index at {} can't be of type {:?}",
+            key_field,
+            path,
+            field_type.value_type()
+        ))),
+    }
+}
+
+/// Terms of the key field equal to the key (take a look under check_key_field).
+fn key_terms(
+    schema: &Schema,
+    key_field: &str,
+    key: &str,
+    index_path: &std::path::PathBuf,
+) -> Result<Vec<Term>, std::io::Error> {
+    let (field, json_path) = match schema.find_field(key_field) {
+        Some(f) => f,
+        None => {
+            return Err(Error::other(format!(
+                "Key field '{}' of {:?} text search index does not exist",
+                key_field, index_path
+            )));
+        }
+    };
+    let invalid_key = |reason: &str| {
+        Error::other(format!(
+            "Key '{}' doesn't fit key field '{}' of {:?} text search index -> {}",
+            key, key_field, index_path, reason
+        ))
+    };
+    match schema.get_field_entry(field).field_type() {
+        FieldType::U64(_) => match key.parse::<u64>() {
+            Ok(k) => Ok(vec![Term::from_field_u64(field, k)]),
+            Err(_) => Err(invalid_key("it is not a u64")),
+        },
+        FieldType::Bool(_) => match key.parse::<bool>() {
+            Ok(k) => Ok(vec![Term::from_field_bool(field, k)]),
+            Err(_) => Err(invalid_key("it is not a bool")),
+        },
+        FieldType::Str(_) => Ok(vec![Term::from_field_text(field, key)]),
+        FieldType::JsonObject(options) => {
+            let expand_dots = options.is_expand_dots_enabled();
+            let mut terms = Vec::new();
+            // NOTE: JSON values can be both, a number (or a boolean) and a string.
+            let mut term = Term::with_capacity(key.len());
+            let mut writer =
+                JsonTermWriter::from_field_and_json_path(field, json_path, expand_dots, &mut term);
+            if let Some(t) = convert_to_fast_value_and_get_term(&mut writer, key) {
+                terms.push(t);
+            }
+            let is_raw = options
+                .get_text_indexing_options()
+                .is_some_and(|indexing| indexing.tokenizer() == "raw");
+            if is_raw {
+                let mut term = Term::with_capacity(key.len());
+                let mut writer = JsonTermWriter::from_field_and_json_path(
+                    field,
+                    json_path,
+                    expand_dots,
+                    &mut term,
+                );
+                writer.set_str(key);
+                terms.push(writer.term().clone());
+            }
+            if terms.is_empty() {
+                return Err(invalid_key(
+                    "it is not a number or a bool and string values are matched only with the raw tokenizer",
                 ));
             }
-        };
-        let mut data: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
-        for (name, field) in input.return_fields.iter().zip(return_fields.iter()) {
-            let field_data = match doc.get_first(*field) {
-                Some(f) => f,
-                None => continue,
-            };
-            // TODO(gitbuda): Shouldn't not just be JSON -> deduce from mappings!
-            let field_as_tantivy_json = match field_data {
-                OwnedValue::Object(f) => f,
-                _ => {
-                    // TODO(gitbuda): Is error here the best?
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("Unable to convert field data to json. Data we have: {:?}", field_data),
-                    ));
-                }
-            };
-            let field_as_json = match serde_json::to_value(field_as_tantivy_json) {
-                Ok(f) => f,
-                Err(_) => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("Unable to convert field data to json"),
-                    ));
-                }
-            };
-            data.insert(name.to_string(), field_as_json);
+            Ok(terms)
         }
-        docs.push(ffi::DocumentOutput {
-            data: match to_string(&data) {
-                Ok(s) => s,
+        field_type => Err(Error::other(format!(
+            "Key field '{}' of {:?} text search index can't be of type {:?}",
+            key_field,
+            index_path,
+            field_type.value_type()
+        ))),
+    }
+}
+
+fn get_document_by_key(
+    searcher: &Searcher,
+    settings: &IndexSettings,
+    key_field: &str,
+    key: &str,
+    index_path: &std::path::PathBuf,
+) -> Result<ffi::DocumentOutput, std::io::Error> {
+    let query = BooleanQuery::new_multiterms_query(key_terms(
+        searcher.schema(),
+        key_field,
+        key,
+        index_path,
+    )?);
+    let query = mvcc::visible_query(settings, Box::new(query), 0);
+    let top_docs = match searcher.search(&query, &TopDocs::with_limit(1)) {
+        Ok(r) => r,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to find a document by key inside {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+    };
+    let data = match top_docs.first() {
+        Some((_, doc_address)) => {
+            let doc: TantivyDocument = match searcher.doc(*doc_address) {
+                Ok(d) => d,
                 Err(e) => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!(
-                            "Unable to serialize {:?} text search index data into a string -> {}",
-                            index_path, e
-                        ),
-                    ));
+                    return Err(Error::other(format!(
+                        "Unable to find document inside {:?} text search index) -> {}",
+                        index_path, e
+                    )));
                 }
-            },
-            score: score,
-        });
+            };
+            Value::Object(stored_document_data(searcher.schema(), &doc)).to_string()
+        }
+        None => String::new(),
+    };
+    Ok(ffi::DocumentOutput {
+        data,
+        score: 0.0,
+        fast_data: "{}".to_string(),
+        index_name: String::new(),
+    })
+}
+
+fn get_documents(
+    context: &mut ffi::Context,
+    keys: Vec<String>,
+) -> Result<Vec<ffi::DocumentOutput>, std::io::Error> {
+    let index_path = &context.tantivyContext.index_path;
+    let key_field = match &context.tantivyContext.settings.key_field {
+        Some(f) => f,
+        None => {
+            return Err(Error::other(format!(
+                "Text search index at {:?} has no key_field setting",
+                index_path
+            )));
+        }
+    };
+    let searcher = context.tantivyContext.index_reader.searcher();
+    let mut docs = Vec::with_capacity(keys.len());
+    for key in &keys {
+        docs.push(get_document_by_key(
+            &searcher,
+            &context.tantivyContext.settings,
+            key_field,
+            key,
+            index_path,
+        )?);
     }
-    Ok(ffi::SearchOutput { docs })
+    Ok(docs)
 }
 
-fn aggregate(
+fn get_document(
     context: &mut ffi::Context,
-    input: &ffi::SearchInput,
+    key: &str,
 ) -> Result<ffi::DocumentOutput, std::io::Error> {
+    let mut docs = get_documents(context, vec![key.to_string()])?;
+    Ok(docs.remove(0))
+}
+
+fn get_num_docs(context: &mut ffi::Context) -> Result<u64, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    let searcher = tantivy_context.index_reader.searcher();
+    mvcc::num_docs(
+        &searcher,
+        &tantivy_context.settings,
+        &tantivy_context.index_path,
+    )
+}
+
+fn get_metrics() -> Result<String, std::io::Error> {
+    Ok(metrics::to_json().to_string())
+}
+
+fn get_index_metrics(context: &mut ffi::Context) -> Result<String, std::io::Error> {
     let index_path = &context.tantivyContext.index_path;
-    let index = &context.tantivyContext.index;
-    let schema = &context.tantivyContext.schema;
-    let reader = &context.tantivyContext.index_reader;
-    
-    let search_fields = search_get_fields(&input.search_fields, schema, index_path)?;
-    let query_parser = QueryParser::for_index(index, search_fields);
-    let query = match query_parser.parse_query(&input.search_query) {
-        Ok(q) => q,
+    let searcher = context.tantivyContext.index_reader.searcher();
+    let space_usage = match searcher.space_usage() {
+        Ok(s) => s,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Unable to create search query {}", e),
-            ));
+            return Err(Error::other(format!(
+                "Unable to compute the size of {:?} text search index -> {}",
+                index_path, e
+            )));
         }
     };
-    let searcher = reader.searcher();
-    let agg_req: Aggregations = serde_json::from_str(&input.aggregation_query)?;
-    let collector = AggregationCollector::from_aggs(agg_req, Default::default());
-    let agg_res: AggregationResults = match searcher.search(&query, &collector) {
+    let num_deleted_docs: u64 = searcher
+        .segment_readers()
+        .iter()
+        .map(|s| s.num_deleted_docs() as u64)
+        .sum();
+    Ok(serde_json::json!({
+        "num_docs": searcher.num_docs(),
+        "num_deleted_docs": num_deleted_docs,
+        "num_segments": searcher.segment_readers().len(),
+        "size_bytes": space_usage.total().get_bytes(),
+    })
+    .to_string())
+}
+
+fn alter_index(context: &mut ffi::Context, mappings: &str) -> Result<(), std::io::Error> {
+    let index_path = context.tantivyContext.index_path.clone();
+    let mappings = match serde_json::from_str::<serde_json::Map<String, Value>>(mappings) {
         Ok(r) => r,
         Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Failed to gather aggregation results for {:?} text search index -> {}",
-                    index_path, e
-                ),
-            ));
+            return Err(Error::other(format!(
+                "Unable to parse mappings for index at {:?} -> {}",
+                index_path, e
+            )));
         }
     };
-    let res: Value = serde_json::to_value(agg_res)?;
-    Ok(ffi::DocumentOutput {
-        data: res.to_string(),
-        score: 0.0, // Aggregation results don't have individual document scores
-    })
+    let new_schema = mvcc::extend_schema(
+        create_index_schema(&mappings)?,
+        &context.tantivyContext.settings,
+        &index_path.to_string_lossy(),
+    )?;
+    let old_schema = context.tantivyContext.schema.clone();
+    let changes = schema_diff::schema_changes(&old_schema, &new_schema);
+    if changes.iter().any(|c| !c.is_compatible()) {
+        return Err(Error::other(format!(
+            "Incompatible mappings change of {:?} text search index (only adding fields is supported, reindex the data instead) -> {}",
+            index_path,
+            schema_diff::describe(&changes)
+        )));
+    }
+    if changes.is_empty() {
+        return Ok(());
+    }
+    // NOTE: Fields are identified by their position -> new fields go after the existing ones.
+    let mut schema_builder = Schema::builder();
+    for (_, field_entry) in old_schema.fields() {
+        schema_builder.add_field(field_entry.clone());
+    }
+    for change in changes {
+        if let schema_diff::SchemaChange::Added(field_entry) = change {
+            schema_builder.add_field(field_entry);
+        }
+    }
+    let schema = schema_builder.build();
+    commit(context)?;
+
+    // NOTE: The writer has to be gone before meta.json is rewritten, otherwise its next commit
+    // would write the old schema back. The placeholder is there only until the index is opened
+    // again.
+    let placeholder_writer = match create_placeholder_index_writer() {
+        Ok(w) => w,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to alter {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+    };
+    // NOTE: The index is opened again below (with a new auto-commit thread).
+    context.tantivyContext.auto_commit = None;
+    let index_writer = std::mem::replace(
+        &mut context
+            .tantivyContext
+            .writer
+            .lock(&index_path)?
+            .index_writer,
+        placeholder_writer,
+    );
+    let alter_res = match index_writer.wait_merging_threads() {
+        Ok(_) => alter_index_meta(&context.tantivyContext.index, &index_path, &schema),
+        Err(e) => Err(e),
+    };
+    // NOTE: If something went wrong, the index is opened as it was so that the context stays
+    // usable.
+    let schema = if alter_res.is_ok() {
+        schema
+    } else {
+        old_schema
+    };
+    let path = index_path.to_string_lossy().to_string();
+    let settings = context.tantivyContext.settings.clone();
+    let resources = context.tantivyContext.resources.clone();
+    let managed = context.tantivyContext.managed;
+    match open_index_with_schema(&path, &schema, settings, &resources) {
+        Ok(c) => {
+            *context = c;
+            context.tantivyContext.managed = managed;
+        }
+        Err(e) => {
+            // NOTE: Writes must not end up in the placeholder writer.
+            if let Ok(mut writer) = context.tantivyContext.writer.state.lock() {
+                writer.unusable = Some(format!(
+                    "it couldn't be opened again after alter_index -> {}",
+                    e
+                ));
+            }
+            return Err(Error::other(format!(
+                "Unable to open {:?} text search index again after altering it -> {}",
+                index_path, e
+            )));
+        }
+    }
+    match alter_res {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::other(format!(
+            "Unable to alter {:?} text search index -> {}",
+            index_path, e
+        ))),
+    }
 }
 
-fn get_num_docs(context: &mut ffi::Context) -> Result<u64, std::io::Error> {
-    let reader = &context.tantivyContext.index_reader;
-    let searcher = reader.searcher();
-    Ok(searcher.num_docs() as u64)
+/// File the altered meta.json is written to before it replaces the current one.
+const ALTER_INDEX_META_FILE: &str = "mgcxx_alter_meta.json";
+
+/// NOTE: meta.json is replaced by a rename -> if anything fails (or the process crashes), the
+/// index stays as it was before the alter (the leftover file is removed once the index is opened
+/// again).
+fn alter_index_meta(
+    index: &Index,
+    index_path: &std::path::Path,
+    schema: &Schema,
+) -> tantivy::Result<()> {
+    let mut meta = index.load_metas()?;
+    meta.schema = schema.clone();
+    let mut buffer = serde_json::to_vec_pretty(&meta)?;
+    buffer.push(b'\n');
+    let altered_meta_path = index_path.join(ALTER_INDEX_META_FILE);
+    let mut altered_meta = std::fs::File::create(&altered_meta_path)?;
+    altered_meta.write_all(&buffer)?;
+    altered_meta.sync_all()?;
+    std::fs::rename(&altered_meta_path, index_path.join("meta.json"))?;
+    // NOTE: The rename is durable only once the directory is synced.
+    index.directory().sync_directory()?;
+    Ok(())
+}
+
+/// Removes what an interrupted alter_index left behind.
+fn alter_index_cleanup(index_path: &std::path::Path) -> Result<(), std::io::Error> {
+    let altered_meta_path = index_path.join(ALTER_INDEX_META_FILE);
+    let cleanup_res = if altered_meta_path.is_dir() {
+        std::fs::remove_dir_all(&altered_meta_path)
+    } else if altered_meta_path.exists() {
+        std::fs::remove_file(&altered_meta_path)
+    } else {
+        Ok(())
+    };
+    match cleanup_res {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::other(format!(
+            "Unable to remove {:?} left by an interrupted alter of {:?} text search index -> {}",
+            altered_meta_path, index_path, e
+        ))),
+    }
+}
+
+/// Contexts owned by [manager::IndexManager] can't be taken over or replaced outside of it.
+fn managed_context_check(context: &ffi::Context, action: &str) -> Result<(), std::io::Error> {
+    if context.tantivyContext.managed {
+        return Err(Error::other(format!(
+            "Text search index at {:?} is owned by an index manager and can't be {} outside of it",
+            context.tantivyContext.index_path, action
+        )));
+    }
+    Ok(())
+}
+
+/// Closes the index and keeps the data on disk.
+/// NOTE: This function takes ownership of the context.
+fn close_index(mut context: ffi::Context, commit_changes: bool) -> Result<(), std::io::Error> {
+    managed_context_check(&context, "closed")?;
+    // NOTE: The auto-commit thread must not commit after the rollback.
+    context.tantivyContext.auto_commit = None;
+    async_commit::wait_all(&mut context.tantivyContext);
+    if commit_changes {
+        commit(&mut context)?;
+    } else {
+        rollback(&mut context)?;
+    }
+    let index_path = context.tantivyContext.index_path;
+    let index_writer = take_index_writer(context.tantivyContext.writer, &index_path)?;
+    // NOTE: Waiting for the merging threads consumes the writer, which releases the lock.
+    if let Err(e) = index_writer.wait_merging_threads() {
+        return Err(Error::other(format!(
+            "Failed to wait for merging threads of {:?} text search index -> {}",
+            index_path, e
+        )));
+    }
+    debug!("Text search index at {:?} closed", index_path);
+    Ok(())
 }
 
 /// Drops the index at the given path.
 /// This will remove the entire directory and all its contents.
 /// NOTE: This function takes ownership of the context.
 fn drop_index(context: ffi::Context) -> Result<(), std::io::Error> {
-    let index_writer = context.tantivyContext.index_writer;
-    
+    managed_context_check(&context, "dropped")?;
+    let mut tantivy_context = context.tantivyContext;
+    tantivy_context.auto_commit = None;
+    async_commit::wait_all(&mut tantivy_context);
+    let index_writer = take_index_writer(tantivy_context.writer, &tantivy_context.index_path)?;
+
     // Wait for all merging threads to finish before dropping the index.
     if let Err(e) = index_writer.wait_merging_threads() {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!("Failed to wait for merging threads: {}", e),
-        ));
+        return Err(Error::other(format!(
+            "Failed to wait for merging threads: {}",
+            e
+        )));
     }
-    let index_path = &context.tantivyContext.index_path;
+    let index_path = &tantivy_context.index_path;
     if index_path.exists() {
         match std::fs::remove_dir_all(index_path) {
             Ok(_) => {
                 debug!("Text search index at {:?} removed", index_path);
             }
             Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "Failed to remove underlying text search index folder -> {}",
-                        e
-                    ),
-                ));
+                return Err(Error::other(format!(
+                    "Failed to remove underlying text search index folder -> {}",
+                    e
+                )));
             }
         }
     } else {
@@ -0,0 +1,143 @@
+//! Logger routing all Rust logs (mgcxx and tantivy) either into the host application callback or
+//! to stderr (env_logger format).
+
+use cxx::{let_cxx_string, UniquePtr};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Error;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::ffi;
+
+// NOTE: The C++ side guarantees the callback is thread-safe (take a look under
+// log_callback.hpp).
+unsafe impl Send for ffi::LogCallback {}
+unsafe impl Sync for ffi::LogCallback {}
+
+struct LoggerState {
+    /// Used to filter records (and to write them to stderr if there is no callback).
+    env_logger: env_logger::Logger,
+    callback: Option<Arc<UniquePtr<ffi::LogCallback>>>,
+}
+
+struct Logger {
+    state: RwLock<Option<LoggerState>>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+static LOGGER_INSTALL: OnceLock<Result<(), String>> = OnceLock::new();
+
+fn to_ffi_level(level: log::Level) -> ffi::LogLevel {
+    match level {
+        log::Level::Error => ffi::LogLevel::Error,
+        log::Level::Warn => ffi::LogLevel::Warn,
+        log::Level::Info => ffi::LogLevel::Info,
+        log::Level::Debug => ffi::LogLevel::Debug,
+        log::Level::Trace => ffi::LogLevel::Trace,
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.state.read() {
+            Ok(state) => match state.as_ref() {
+                Some(state) => state.env_logger.enabled(metadata),
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let callback = {
+            let state = match self.state.read() {
+                Ok(s) => s,
+                Err(_) => return,
+            };
+            let state = match state.as_ref() {
+                Some(s) => s,
+                None => return,
+            };
+            if !state.env_logger.matches(record) {
+                return;
+            }
+            match &state.callback {
+                Some(callback) => callback.clone(),
+                None => {
+                    state.env_logger.log(record);
+                    return;
+                }
+            }
+        };
+        // NOTE: The callback is called without holding the lock because it may call init (which
+        // would wait for the lock forever).
+        let_cxx_string!(target = record.target());
+        let_cxx_string!(message = record.args().to_string());
+        callback.log(to_ffi_level(record.level()), &target, &message);
+    }
+
+    fn flush(&self) {
+        if let Ok(state) = self.state.read() {
+            if let Some(state) = state.as_ref() {
+                state.env_logger.flush();
+            }
+        }
+    }
+}
+
+/// log_level is one of off|error|warn|info|debug|trace (case insensitive), if empty, the
+/// RUST_LOG environment variable is used (warn by default).
+fn create_env_logger(log_level: &str) -> Result<env_logger::Logger, std::io::Error> {
+    if log_level.is_empty() {
+        return Ok(env_logger::Builder::from_env(
+            env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "warn"),
+        )
+        .build());
+    }
+    match LevelFilter::from_str(log_level) {
+        Ok(level) => Ok(env_logger::Builder::new().filter_level(level).build()),
+        Err(_) => Err(Error::other(format!(
+            "Unknown text search log level '{}', use one of off|error|warn|info|debug|trace",
+            log_level
+        ))),
+    }
+}
+
+/// Installs the logger on the first call, every subsequent call just replaces the log level and
+/// the callback.
+pub fn init(
+    log_level: &str,
+    log_callback: UniquePtr<ffi::LogCallback>,
+) -> Result<(), std::io::Error> {
+    let env_logger = create_env_logger(log_level)?;
+    let max_level = env_logger.filter();
+    let logger = LOGGER.get_or_init(|| Logger {
+        state: RwLock::new(None),
+    });
+    match logger.state.write() {
+        Ok(mut state) => {
+            *state = Some(LoggerState {
+                env_logger,
+                callback: if log_callback.is_null() {
+                    None
+                } else {
+                    Some(Arc::new(log_callback))
+                },
+            })
+        }
+        Err(_) => {
+            return Err(Error::other(
+                "Unable to initialize tantivy (text search engine) logger because of a poisoned lock",
+            ));
+        }
+    }
+    let install_res = LOGGER_INSTALL.get_or_init(|| match log::set_logger(logger) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    });
+    if let Err(e) = install_res {
+        return Err(Error::other(format!("Unable to initialize tantivy (text search engine) logger because another logger is already installed in this process -> {}", e)));
+    }
+    log::set_max_level(max_level);
+    Ok(())
+}
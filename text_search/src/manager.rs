@@ -0,0 +1,355 @@
+//! Registry of named indices sharing the writer memory budget and the search thread pool.
+
+use log::warn;
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tantivy::Executor;
+
+use crate::{
+    close_index, context_search_interruptible, drop_index, ffi, open_index, search_merge_docs,
+    searcher_search, set_writer_resources, CancellationToken, IndexResources, Interruption,
+};
+
+/// Same as the minimum tantivy requires for each indexing thread (not exported by tantivy).
+const WRITER_MEMORY_PER_THREAD_MIN: usize = 15_000_000;
+
+/// Directories of the indices managed by all index managers (together with the index names),
+/// one writer per directory is enforced across the managers.
+static MANAGED_DIRECTORIES: Mutex<BTreeMap<PathBuf, String>> = Mutex::new(BTreeMap::new());
+
+/// Removes the directory from [MANAGED_DIRECTORIES] once dropped.
+struct DirectoryRegistration {
+    directory: PathBuf,
+}
+
+impl DirectoryRegistration {
+    fn register(directory: PathBuf, name: &str) -> Result<DirectoryRegistration, std::io::Error> {
+        let mut directories = MANAGED_DIRECTORIES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(other) = directories.get(&directory) {
+            return Err(Error::other(format!(
+                "Text search index directory {:?} is already used by index '{}' (only one writer per directory is allowed)",
+                directory, other
+            )));
+        }
+        directories.insert(directory.clone(), name.to_string());
+        Ok(DirectoryRegistration { directory })
+    }
+}
+
+impl Drop for DirectoryRegistration {
+    fn drop(&mut self) {
+        MANAGED_DIRECTORIES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.directory);
+    }
+}
+
+struct ManagedIndex {
+    context: ffi::Context,
+    /// NOTE: Dropped after the context (the writer releases the directory lock first).
+    registration: DirectoryRegistration,
+}
+
+pub struct IndexManager {
+    config: ffi::IndexManagerConfig,
+    search_executor: Option<Arc<Executor>>,
+    indices: BTreeMap<String, ManagedIndex>,
+}
+
+/// Used to enforce one writer per directory.
+fn normalize_directory(path: &Path) -> Result<PathBuf, std::io::Error> {
+    let normalized = if path.exists() {
+        std::fs::canonicalize(path)
+    } else {
+        std::path::absolute(path)
+    };
+    match normalized {
+        Ok(p) => Ok(p),
+        Err(e) => Err(Error::other(format!(
+            "Unable to resolve text search index directory {:?} -> {}",
+            path, e
+        ))),
+    }
+}
+
+pub fn create_index_manager(
+    config: &ffi::IndexManagerConfig,
+) -> Result<Box<IndexManager>, std::io::Error> {
+    let search_executor = if config.search_threads == 0 {
+        None
+    } else {
+        match Executor::multi_thread(config.search_threads, "mgcxx-text-search-") {
+            Ok(e) => Some(Arc::new(e)),
+            Err(e) => {
+                return Err(Error::other(format!(
+                    "Unable to create text search thread pool -> {}",
+                    e
+                )));
+            }
+        }
+    };
+    Ok(Box::new(IndexManager {
+        config: config.clone(),
+        search_executor,
+        indices: BTreeMap::new(),
+    }))
+}
+
+impl IndexManager {
+    /// Returns the resources of each writer once num_indices indices are managed, None if the
+    /// writer memory budget is not enough for all of them.
+    fn writer_resources(&self, num_indices: usize) -> Option<IndexResources> {
+        let budget = self.config.writer_memory_budget;
+        let writer_memory = if self.config.writer_memory_per_index != 0 {
+            self.config.writer_memory_per_index
+        } else if budget != 0 {
+            budget / num_indices.max(1)
+        } else {
+            50_000_000
+        };
+        if budget != 0 && writer_memory.saturating_mul(num_indices) > budget {
+            return None;
+        }
+        if writer_memory < WRITER_MEMORY_PER_THREAD_MIN && self.config.writer_memory_per_index == 0
+        {
+            return None;
+        }
+        Some(IndexResources {
+            writer_memory,
+            writer_threads: (writer_memory / WRITER_MEMORY_PER_THREAD_MIN)
+                .clamp(1, self.config.writer_threads_per_index.max(1)),
+            search_executor: self.search_executor.clone(),
+        })
+    }
+
+    /// Gives all writers the resources they have once the number of indices changed (only if
+    /// the writer memory budget is shared).
+    fn share_writer_resources(&mut self) {
+        let resources = match self.writer_resources(self.indices.len()) {
+            Some(r) => r,
+            None => return,
+        };
+        for (name, index) in self.indices.iter_mut() {
+            // NOTE: The writer is unusable if it couldn't be created again, the other ones are
+            // still resized.
+            if let Err(e) =
+                set_writer_resources(&mut index.context.tantivyContext, resources.clone())
+            {
+                warn!(
+                    "Unable to change writer resources of text search index '{}' -> {}",
+                    name, e
+                );
+            }
+        }
+    }
+
+    fn take_index(&mut self, name: &String) -> Result<ManagedIndex, std::io::Error> {
+        match self.indices.remove(name) {
+            Some(mut i) => {
+                i.context.tantivyContext.managed = false;
+                Ok(i)
+            }
+            None => Err(Error::other(format!(
+                "Text search index '{}' is not managed by this index manager",
+                name
+            ))),
+        }
+    }
+
+    pub fn create_index(
+        &mut self,
+        name: &String,
+        path: &String,
+        config: &ffi::IndexConfig,
+    ) -> Result<(), std::io::Error> {
+        if self.indices.contains_key(name) {
+            return Err(Error::other(format!(
+                "Text search index '{}' already exists",
+                name
+            )));
+        }
+        let registration =
+            DirectoryRegistration::register(normalize_directory(Path::new(path))?, name)?;
+        let num_indices = self.indices.len() + 1;
+        let resources = match self.writer_resources(num_indices) {
+            Some(r) => r,
+            None => {
+                return Err(Error::other(format!(
+                    "Unable to create text search index '{}' because the writer memory budget of {} bytes is exhausted ({} writers would get less than {} bytes each)",
+                    name,
+                    self.config.writer_memory_budget,
+                    num_indices,
+                    self.config
+                        .writer_memory_per_index
+                        .max(WRITER_MEMORY_PER_THREAD_MIN)
+                )));
+            }
+        };
+        let mut context = open_index(path, config, &resources)?;
+        context.tantivyContext.managed = true;
+        self.indices.insert(
+            name.to_string(),
+            ManagedIndex {
+                context,
+                registration,
+            },
+        );
+        self.share_writer_resources();
+        Ok(())
+    }
+
+    pub fn get_index<'a>(
+        &'a mut self,
+        name: &String,
+    ) -> Result<&'a mut ffi::Context, std::io::Error> {
+        match self.indices.get_mut(name) {
+            Some(i) => Ok(&mut i.context),
+            None => Err(Error::other(format!(
+                "Text search index '{}' is not managed by this index manager",
+                name
+            ))),
+        }
+    }
+
+    pub fn list_indexes(&self) -> Vec<String> {
+        self.indices.keys().cloned().collect()
+    }
+
+    pub fn close_index(
+        &mut self,
+        name: &String,
+        commit_changes: bool,
+    ) -> Result<(), std::io::Error> {
+        let index = self.take_index(name)?;
+        // NOTE: The directory is released once the writer is gone.
+        let close_res = close_index(index.context, commit_changes);
+        drop(index.registration);
+        self.share_writer_resources();
+        close_res
+    }
+
+    pub fn drop_index(&mut self, name: &String) -> Result<(), std::io::Error> {
+        let index = self.take_index(name)?;
+        let drop_res = drop_index(index.context);
+        drop(index.registration);
+        self.share_writer_resources();
+        drop_res
+    }
+
+    pub fn search_many(
+        &mut self,
+        index_names: Vec<String>,
+        input: &ffi::SearchInput,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        self.search_many_interruptible(index_names, input, None)
+    }
+
+    pub fn search_many_cancellable(
+        &mut self,
+        index_names: Vec<String>,
+        input: &ffi::SearchInput,
+        token: &CancellationToken,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        self.search_many_interruptible(index_names, input, Some(token))
+    }
+
+    fn search_many_interruptible(
+        &mut self,
+        index_names: Vec<String>,
+        input: &ffi::SearchInput,
+        token: Option<&CancellationToken>,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        let index_names = if index_names.is_empty() {
+            self.list_indexes()
+        } else {
+            index_names
+        };
+        // NOTE: A duplicated name would return (and count) the same hits twice.
+        let mut searches = Vec::with_capacity(index_names.len());
+        for name in index_names {
+            if searches.iter().any(|(n, _)| *n == name) {
+                continue;
+            }
+            let context = match self.indices.get(&name) {
+                Some(i) => &i.context,
+                None => {
+                    return Err(Error::other(format!(
+                        "Text search index '{}' is not managed by this index manager",
+                        name
+                    )));
+                }
+            };
+            searches.push((name, context));
+        }
+        search_merged(searches, input, token)
+    }
+}
+
+/// Runs the same search against all the contexts (tagging hits by the given names) and merges
+/// the hits by score.
+fn search_merged(
+    searches: Vec<(String, &ffi::Context)>,
+    input: &ffi::SearchInput,
+    token: Option<&CancellationToken>,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    // NOTE: A single interruption -> the timeout is for all the searches together.
+    let interruption = Interruption::new(input, token);
+    let mut docs: Vec<ffi::DocumentOutput> = Vec::new();
+    let mut total_count = 0;
+    let mut warnings: Vec<String> = Vec::new();
+    for (name, context) in &searches {
+        // NOTE: Each index returns at most limit hits, which is enough for the merged top.
+        let output = context_search_interruptible(context, input, &interruption, searcher_search)?;
+        total_count += output.total_count;
+        warnings.extend(
+            output
+                .warnings
+                .into_iter()
+                .map(|w| format!("{}: {}", name, w)),
+        );
+        docs.extend(output.docs.into_iter().map(|mut doc| {
+            doc.index_name = name.to_string();
+            doc
+        }));
+    }
+    // NOTE: Equally scored hits keep the searches order.
+    search_merge_docs(&mut docs, input.effective_limit());
+    Ok(ffi::SearchOutput {
+        docs,
+        total_count,
+        warnings,
+    })
+}
+
+/// Same as [IndexManager::search_many], hits are tagged with the index paths.
+pub fn search_many(
+    contexts: &[ffi::Context],
+    input: &ffi::SearchInput,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    search_many_interruptible(contexts, input, None)
+}
+
+pub fn search_many_cancellable(
+    contexts: &[ffi::Context],
+    input: &ffi::SearchInput,
+    token: &CancellationToken,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    search_many_interruptible(contexts, input, Some(token))
+}
+
+fn search_many_interruptible(
+    contexts: &[ffi::Context],
+    input: &ffi::SearchInput,
+    token: Option<&CancellationToken>,
+) -> Result<ffi::SearchOutput, std::io::Error> {
+    let searches = contexts
+        .iter()
+        .map(|c| (c.tantivyContext.index_path.display().to_string(), c))
+        .collect();
+    search_merged(searches, input, token)
+}
@@ -0,0 +1,152 @@
+//! Process wide metrics (counters and latency histograms) of all text search indices.
+
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tantivy::merge_policy::{MergeCandidate, MergePolicy};
+use tantivy::SegmentMeta;
+
+/// Upper bounds (inclusive, in microseconds) of the histogram buckets, the last (implicit) bucket
+/// is +Inf.
+const BUCKET_BOUNDS_US: [u64; 12] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 1_000_000, 10_000_000,
+];
+
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS_US.len() + 1],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Histogram {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKET_BOUNDS_US.len() + 1],
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let us = duration.as_micros() as u64;
+        let bucket = BUCKET_BOUNDS_US
+            .iter()
+            .position(|bound| us <= *bound)
+            .unwrap_or(BUCKET_BOUNDS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    /// Buckets are cumulative (Prometheus style), le is null for the +Inf bucket.
+    fn to_json(&self) -> Value {
+        let mut cumulative = 0;
+        let mut buckets = Vec::with_capacity(self.buckets.len());
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            buckets.push(json!({
+                "le": BUCKET_BOUNDS_US.get(i),
+                "count": cumulative,
+            }));
+        }
+        json!({
+            "count": self.count.load(Ordering::Relaxed),
+            "sum_us": self.sum_us.load(Ordering::Relaxed),
+            "buckets": buckets,
+        })
+    }
+}
+
+pub struct Metrics {
+    /// search, regex_search, count and aggregate calls.
+    pub searches: AtomicU64,
+    pub search_errors: AtomicU64,
+    pub search_latency: Histogram,
+    pub documents_added: AtomicU64,
+    /// Delete queries (each one can delete any number of documents), not deleted documents.
+    pub delete_queries: AtomicU64,
+    pub commits: AtomicU64,
+    pub commit_errors: AtomicU64,
+    pub commit_duration: Histogram,
+    pub reload_duration: Histogram,
+    pub rollbacks: AtomicU64,
+    /// Merges scheduled by the merge policy, they run (and finish) in the background.
+    pub merges_scheduled: AtomicU64,
+    /// Segments of the scheduled merges.
+    pub merge_scheduled_segments: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    searches: AtomicU64::new(0),
+    search_errors: AtomicU64::new(0),
+    search_latency: Histogram::new(),
+    documents_added: AtomicU64::new(0),
+    delete_queries: AtomicU64::new(0),
+    commits: AtomicU64::new(0),
+    commit_errors: AtomicU64::new(0),
+    commit_duration: Histogram::new(),
+    reload_duration: Histogram::new(),
+    rollbacks: AtomicU64::new(0),
+    merges_scheduled: AtomicU64::new(0),
+    merge_scheduled_segments: AtomicU64::new(0),
+};
+
+pub fn inc(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Runs a read operation (search, count, ...) and records its latency and outcome.
+pub fn measure_search<T, E>(f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let start = Instant::now();
+    let res = f();
+    METRICS.search_latency.observe(start.elapsed());
+    inc(&METRICS.searches);
+    if res.is_err() {
+        inc(&METRICS.search_errors);
+    }
+    res
+}
+
+pub fn to_json() -> Value {
+    let m = &METRICS;
+    json!({
+        "searches_total": m.searches.load(Ordering::Relaxed),
+        "search_errors_total": m.search_errors.load(Ordering::Relaxed),
+        "search_latency": m.search_latency.to_json(),
+        "documents_added_total": m.documents_added.load(Ordering::Relaxed),
+        "delete_queries_total": m.delete_queries.load(Ordering::Relaxed),
+        "commits_total": m.commits.load(Ordering::Relaxed),
+        "commit_errors_total": m.commit_errors.load(Ordering::Relaxed),
+        "commit_duration": m.commit_duration.to_json(),
+        "reload_duration": m.reload_duration.to_json(),
+        "rollbacks_total": m.rollbacks.load(Ordering::Relaxed),
+        "merges_scheduled_total": m.merges_scheduled.load(Ordering::Relaxed),
+        "merge_scheduled_segments_total": m.merge_scheduled_segments.load(Ordering::Relaxed),
+    })
+}
+
+/// Wraps the actual merge policy to count the merges it schedules (tantivy doesn't report when
+/// a background merge finishes).
+#[derive(Debug)]
+pub struct MeteredMergePolicy<P: MergePolicy> {
+    inner: P,
+}
+
+impl<P: MergePolicy> MeteredMergePolicy<P> {
+    pub fn new(inner: P) -> MeteredMergePolicy<P> {
+        MeteredMergePolicy { inner }
+    }
+}
+
+impl<P: MergePolicy> MergePolicy for MeteredMergePolicy<P> {
+    fn compute_merge_candidates(&self, segments: &[SegmentMeta]) -> Vec<MergeCandidate> {
+        let candidates = self.inner.compute_merge_candidates(segments);
+        for candidate in &candidates {
+            inc(&METRICS.merges_scheduled);
+            METRICS
+                .merge_scheduled_segments
+                .fetch_add(candidate.0.len() as u64, Ordering::Relaxed);
+        }
+        candidates
+    }
+}
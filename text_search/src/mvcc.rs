@@ -0,0 +1,455 @@
+//! Document versions visible at a given (transaction) timestamp, enabled by the mvcc setting.
+//!
+//! Each document version carries created_ts (when it was added) and deleted_ts (when it was
+//! deleted, u64::MAX while it's alive). A deletion doesn't remove the document, it replaces the
+//! alive version with a copy that has deleted_ts set, so older transactions still see it.
+//! Removing documents (delete_document) is rejected for the same reason.
+
+use std::collections::HashSet;
+use std::io::Error;
+use std::ops::Bound;
+use tantivy::collector::{Count, DocSetCollector};
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, RangeQuery};
+use tantivy::schema::{Field, NumericOptions, Schema};
+use tantivy::{
+    DocAddress, Index, IndexReader, IndexWriter, ReloadPolicy, Searcher, TantivyDocument,
+};
+
+use crate::{
+    delete_parse_query, ffi, finish_operation, metrics, wal, IndexSettings, IndexWriterState,
+    TantivyContext,
+};
+
+pub const CREATED_TS_FIELD: &str = "created_ts";
+pub const DELETED_TS_FIELD: &str = "deleted_ts";
+
+pub fn is_mvcc_field(name: &str) -> bool {
+    name == CREATED_TS_FIELD || name == DELETED_TS_FIELD
+}
+
+/// Versions changed since the last commit, the committed searcher doesn't know about them.
+/// Without this a document deleted twice before a commit would get two deleted versions and the
+/// alive version of an update would be marked from the stale committed one.
+#[derive(Default)]
+pub struct PendingVersions {
+    /// Alive versions added since the last delete_query_at (moved into the overlay by it).
+    added: Vec<TantivyDocument>,
+    /// In-memory index of the pending alive versions, created by the first delete_query_at.
+    overlay: Option<(IndexWriter, IndexReader)>,
+    /// Committed alive versions which are already marked as deleted (the reader is reloaded only
+    /// by commits, so the addresses don't change until then).
+    marked: HashSet<DocAddress>,
+    /// delete_all_documents removed all the committed versions.
+    committed_removed: bool,
+}
+
+impl PendingVersions {
+    pub fn add(&mut self, document: TantivyDocument) {
+        self.added.push(document);
+    }
+
+    pub fn delete_all(&mut self) {
+        *self = PendingVersions {
+            committed_removed: true,
+            ..Default::default()
+        };
+    }
+
+    /// Moves the added versions into the overlay and makes all its changes visible.
+    fn overlay_searcher(
+        &mut self,
+        schema: &Schema,
+        index_path: &std::path::Path,
+    ) -> Result<Option<Searcher>, std::io::Error> {
+        if self.overlay.is_none() {
+            if self.added.is_empty() {
+                return Ok(None);
+            }
+            let index = Index::create_in_ram(schema.clone());
+            let writer = match index.writer_with_num_threads(1, 15_000_000) {
+                Ok(w) => w,
+                Err(e) => {
+                    return Err(Error::other(format!(
+                        "Unable to create pending versions overlay of {:?} text search index -> {}",
+                        index_path, e
+                    )));
+                }
+            };
+            let reader = match index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::Manual)
+                .try_into()
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    return Err(Error::other(format!(
+                        "Unable to create pending versions overlay reader of {:?} text search index -> {}",
+                        index_path, e
+                    )));
+                }
+            };
+            self.overlay = Some((writer, reader));
+        }
+        let (writer, reader) = match self.overlay.as_mut() {
+            Some(o) => o,
+            None => return Err(Error::other("pending versions overlay is missing")),
+        };
+        for document in self.added.drain(..) {
+            if let Err(e) = writer.add_document(document) {
+                return Err(Error::other(format!("Unable to add document -> {}", e)));
+            }
+        }
+        if let Err(e) = writer.commit() {
+            return Err(Error::other(format!(
+                "Unable to refresh pending versions overlay of {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+        if let Err(e) = reader.reload() {
+            return Err(Error::other(format!(
+                "Unable to reload pending versions overlay reader of {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+        Ok(Some(reader.searcher()))
+    }
+}
+
+/// Adds the version fields to the schema created from the mappings.
+pub fn extend_schema(
+    schema: Schema,
+    settings: &IndexSettings,
+    index_path: &str,
+) -> Result<Schema, std::io::Error> {
+    if !settings.mvcc {
+        return Ok(schema);
+    }
+    let mut schema_builder = Schema::builder();
+    for (_, field_entry) in schema.fields() {
+        if is_mvcc_field(field_entry.name()) {
+            return Err(Error::other(format!(
+                "Field '{}' of text search index at {} is reserved because of the mvcc setting",
+                field_entry.name(),
+                index_path
+            )));
+        }
+        // NOTE: Deleted versions are copies of the stored fields (take a look under
+        // delete_document_at).
+        if (field_entry.is_indexed() || field_entry.is_fast()) && !field_entry.is_stored() {
+            return Err(Error::other(format!(
+                "Field '{}' of text search index at {} has to be stored because of the mvcc setting",
+                field_entry.name(),
+                index_path
+            )));
+        }
+        schema_builder.add_field(field_entry.clone());
+    }
+    let options = NumericOptions::default()
+        .set_indexed()
+        .set_fast()
+        .set_stored();
+    schema_builder.add_u64_field(CREATED_TS_FIELD, options.clone());
+    schema_builder.add_u64_field(DELETED_TS_FIELD, options);
+    Ok(schema_builder.build())
+}
+
+/// Sets the version fields of a new document, unless the document already has them (e.g. it's
+/// copied from another index).
+pub fn stamp_document(
+    schema: &Schema,
+    settings: &IndexSettings,
+    document: &mut TantivyDocument,
+    timestamp: u64,
+) {
+    if !settings.mvcc {
+        return;
+    }
+    if let Ok(field) = schema.get_field(CREATED_TS_FIELD) {
+        if document.get_first(field).is_none() {
+            document.add_u64(field, timestamp);
+        }
+    }
+    if let Ok(field) = schema.get_field(DELETED_TS_FIELD) {
+        if document.get_first(field).is_none() {
+            document.add_u64(field, u64::MAX);
+        }
+    }
+}
+
+/// Restricts the query to the versions visible at the given timestamp, 0 means the alive ones.
+pub fn visible_query(
+    settings: &IndexSettings,
+    query: Box<dyn Query>,
+    as_of_timestamp: u64,
+) -> Box<dyn Query> {
+    if !settings.mvcc {
+        return query;
+    }
+    let mut clauses = vec![(Occur::Must, query)];
+    if as_of_timestamp == 0 {
+        clauses.push((
+            Occur::Must,
+            Box::new(RangeQuery::new_u64_bounds(
+                DELETED_TS_FIELD.to_string(),
+                Bound::Included(u64::MAX),
+                Bound::Unbounded,
+            )),
+        ));
+    } else {
+        clauses.push((
+            Occur::Must,
+            Box::new(RangeQuery::new_u64_bounds(
+                CREATED_TS_FIELD.to_string(),
+                Bound::Unbounded,
+                Bound::Included(as_of_timestamp),
+            )),
+        ));
+        clauses.push((
+            Occur::Must,
+            Box::new(RangeQuery::new_u64_bounds(
+                DELETED_TS_FIELD.to_string(),
+                Bound::Excluded(as_of_timestamp),
+                Bound::Unbounded,
+            )),
+        ));
+    }
+    Box::new(BooleanQuery::new(clauses))
+}
+
+/// Number of the alive versions (all documents without the mvcc setting).
+pub fn num_docs(
+    searcher: &Searcher,
+    settings: &IndexSettings,
+    index_path: &std::path::Path,
+) -> Result<u64, std::io::Error> {
+    if !settings.mvcc {
+        return Ok(searcher.num_docs());
+    }
+    let query = visible_query(settings, Box::new(AllQuery), 0);
+    match searcher.search(query.as_ref(), &Count) {
+        Ok(count) => Ok(count as u64),
+        Err(e) => Err(Error::other(format!(
+            "Unable to count documents of {:?} text search index -> {}",
+            index_path, e
+        ))),
+    }
+}
+
+pub fn mvcc_settings_check(
+    settings: &IndexSettings,
+    index_path: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    if !settings.mvcc {
+        return Err(Error::other(format!(
+            "Text search index at {:?} has no mvcc setting",
+            index_path
+        )));
+    }
+    Ok(())
+}
+
+/// Removing documents would lose the versions older transactions still see.
+pub fn physical_delete_check(
+    settings: &IndexSettings,
+    index_path: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    if settings.mvcc {
+        return Err(Error::other(format!(
+            "Documents of text search index at {:?} have to be deleted at a timestamp because of the mvcc setting (take a look under delete_document_at)",
+            index_path
+        )));
+    }
+    Ok(())
+}
+
+pub fn delete_document_at(
+    context: &mut ffi::Context,
+    input: &ffi::SearchInput,
+    timestamp: u64,
+    skip_commit: bool,
+) -> Result<u64, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    let index_path = &tantivy_context.index_path;
+    mvcc_settings_check(&tantivy_context.settings, index_path)?;
+    let query = delete_parse_query(&tantivy_context.index, input, index_path)?;
+    let mut writer = tantivy_context.writer.lock(index_path)?;
+    delete_query_at(
+        tantivy_context,
+        &mut writer,
+        query,
+        wal::WalDeleteQuery::new(input),
+        timestamp,
+        skip_commit,
+    )
+}
+
+/// Copy of the version with deleted_ts set.
+fn deleted_version(
+    document: &TantivyDocument,
+    deleted_ts_field: Field,
+    timestamp: u64,
+) -> TantivyDocument {
+    let mut deleted_document = TantivyDocument::new();
+    for field_value in document.field_values() {
+        if field_value.field() != deleted_ts_field {
+            deleted_document.add_field_value(field_value.field(), field_value.value().clone());
+        }
+    }
+    deleted_document.add_u64(deleted_ts_field, timestamp);
+    deleted_document
+}
+
+/// Deleted versions of the found documents, skips the ones rejected by the filter.
+fn deleted_versions(
+    searcher: &Searcher,
+    query: &dyn Query,
+    deleted_ts_field: Field,
+    timestamp: u64,
+    index_path: &std::path::Path,
+    mut filter: impl FnMut(DocAddress) -> bool,
+) -> Result<Vec<TantivyDocument>, std::io::Error> {
+    let doc_addresses = match searcher.search(query, &DocSetCollector) {
+        Ok(r) => r,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to find documents to delete inside {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+    };
+    let mut deleted_documents = Vec::with_capacity(doc_addresses.len());
+    for doc_address in doc_addresses {
+        if !filter(doc_address) {
+            continue;
+        }
+        let doc: TantivyDocument = match searcher.doc(doc_address) {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(Error::other(format!(
+                    "Unable to find document inside {:?} text search index) -> {}",
+                    index_path, e
+                )));
+            }
+        };
+        deleted_documents.push(deleted_version(&doc, deleted_ts_field, timestamp));
+    }
+    Ok(deleted_documents)
+}
+
+/// Marks the alive versions matching the query as deleted at the given timestamp, both the
+/// committed ones and the ones added since the last commit.
+pub fn delete_query_at(
+    tantivy_context: &TantivyContext,
+    writer: &mut IndexWriterState,
+    query: Box<dyn Query>,
+    wal_query: wal::WalDeleteQuery,
+    timestamp: u64,
+    skip_commit: bool,
+) -> Result<u64, std::io::Error> {
+    let index_path = &tantivy_context.index_path;
+    let schema = &tantivy_context.schema;
+    let query = visible_query(&tantivy_context.settings, query, 0);
+    let deleted_ts_field = match schema.get_field(DELETED_TS_FIELD) {
+        Ok(f) => f,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "{} inside {:?} text search index",
+                e, index_path
+            )));
+        }
+    };
+    let pending = &mut writer.mvcc_pending;
+    let mut deleted_documents = Vec::new();
+    // NOTE: The committed versions deleted by an earlier delete_query_at (or delete_all) are
+    // already removed from the writer, they can't be marked again.
+    if !pending.committed_removed {
+        let marked = &mut pending.marked;
+        deleted_documents = deleted_versions(
+            &tantivy_context.index_reader.searcher(),
+            query.as_ref(),
+            deleted_ts_field,
+            timestamp,
+            index_path,
+            |doc_address| marked.insert(doc_address),
+        )?;
+    }
+    if let Some(searcher) = pending.overlay_searcher(schema, index_path)? {
+        deleted_documents.extend(deleted_versions(
+            &searcher,
+            query.as_ref(),
+            deleted_ts_field,
+            timestamp,
+            index_path,
+            |_| true,
+        )?);
+        if let Some((overlay_writer, _)) = pending.overlay.as_mut() {
+            if let Err(e) = overlay_writer.delete_query(query.box_clone()) {
+                return Err(Error::other(format!(
+                    "Unable to delete document from pending versions overlay of {:?} text search index -> {}",
+                    index_path, e
+                )));
+            }
+        }
+    }
+    // NOTE: The delete affects only the documents added before it -> the deleted versions stay.
+    let mut opstamp = match writer.index_writer.delete_query(query) {
+        Ok(o) => o,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to delete document from text search index at {:?} -> {}",
+                index_path, e
+            )));
+        }
+    };
+    metrics::inc(&metrics::METRICS.delete_queries);
+    for deleted_document in deleted_documents {
+        opstamp = match writer.index_writer.add_document(deleted_document) {
+            Ok(o) => o,
+            Err(e) => {
+                return Err(Error::other(format!("Unable to add document -> {}", e)));
+            }
+        };
+    }
+    // NOTE: Replaying the whole operation marks the same documents (the log is cleared by each
+    // commit, so the committed documents are the same).
+    finish_operation(tantivy_context, writer, opstamp, skip_commit, || {
+        wal::WalOperation::DeleteAt {
+            query: wal_query,
+            timestamp,
+        }
+    })?;
+    Ok(opstamp)
+}
+
+pub fn purge_deleted(
+    context: &mut ffi::Context,
+    timestamp: u64,
+    skip_commit: bool,
+) -> Result<u64, std::io::Error> {
+    mvcc_settings_check(
+        &context.tantivyContext.settings,
+        &context.tantivyContext.index_path,
+    )?;
+    // NOTE: u64::MAX marks the alive versions.
+    let query = RangeQuery::new_u64_bounds(
+        DELETED_TS_FIELD.to_string(),
+        Bound::Unbounded,
+        Bound::Included(timestamp.min(u64::MAX - 1)),
+    );
+    let tantivy_context = &context.tantivyContext;
+    let index_path = &tantivy_context.index_path;
+    let mut writer = tantivy_context.writer.lock(index_path)?;
+    match writer.index_writer.delete_query(Box::new(query)) {
+        Ok(opstamp) => {
+            metrics::inc(&metrics::METRICS.delete_queries);
+            finish_operation(tantivy_context, &mut writer, opstamp, skip_commit, || {
+                wal::WalOperation::PurgeDeleted { timestamp }
+            })?;
+            Ok(opstamp)
+        }
+        Err(e) => Err(Error::other(format!(
+            "Unable to purge deleted documents from text search index at {:?} -> {}",
+            index_path, e
+        ))),
+    }
+}
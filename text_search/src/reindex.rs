@@ -0,0 +1,235 @@
+//! Rebuilding an index into a new one (e.g. after an incompatible mappings change) in the
+//! background.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tantivy::{Searcher, TantivyDocument};
+
+use crate::{
+    add_document, commit, ffi, managed_context_check, mvcc, open_index, stored_document_data,
+};
+
+/// Parsed transform argument of [crate::ffi::reindex].
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReindexTransform {
+    /// old field name -> new field name
+    #[serde(default)]
+    rename: HashMap<String, String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+impl ReindexTransform {
+    fn parse(transform: &str) -> Result<ReindexTransform, std::io::Error> {
+        if transform.is_empty() {
+            return Ok(ReindexTransform::default());
+        }
+        match serde_json::from_str(transform) {
+            Ok(t) => Ok(t),
+            Err(e) => Err(Error::other(format!(
+                "Unable to parse reindex transform -> {}",
+                e
+            ))),
+        }
+    }
+
+    fn apply(&self, data: &mut serde_json::Map<String, Value>) {
+        for name in &self.remove {
+            data.remove(name);
+        }
+        for (old_name, new_name) in &self.rename {
+            if let Some(value) = data.remove(old_name) {
+                data.insert(new_name.to_string(), value);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ReindexState {
+    processed: AtomicU64,
+    total: u64,
+    done: AtomicBool,
+    cancelled: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+pub struct ReindexHandle {
+    state: Arc<ReindexState>,
+    thread: Option<JoinHandle<Option<ffi::Context>>>,
+}
+
+impl ReindexHandle {
+    pub fn progress(&self) -> ffi::ReindexProgress {
+        let error = match self.state.error.lock() {
+            Ok(e) => e.clone().unwrap_or_default(),
+            Err(_) => "reindex state is poisoned".to_string(),
+        };
+        ffi::ReindexProgress {
+            processed: self.state.processed.load(Ordering::Relaxed),
+            total: self.state.total,
+            done: self.state.done.load(Ordering::Acquire),
+            error,
+        }
+    }
+}
+
+impl Drop for ReindexHandle {
+    /// Stops the unfinished reindex, the target index stays on disk.
+    fn drop(&mut self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn reindex_documents(
+    searcher: &Searcher,
+    source_mvcc: bool,
+    target: &mut ffi::Context,
+    transform: &ReindexTransform,
+    state: &ReindexState,
+) -> Result<(), std::io::Error> {
+    for segment_reader in searcher.segment_readers() {
+        let store_reader = segment_reader.get_store_reader(1)?;
+        for doc in store_reader.iter::<TantivyDocument>(segment_reader.alive_bitset()) {
+            if state.cancelled.load(Ordering::Relaxed) {
+                return Err(Error::other("reindex cancelled"));
+            }
+            let doc = match doc {
+                Ok(d) => d,
+                Err(e) => {
+                    return Err(Error::other(format!(
+                        "Unable to read a document from the source index -> {}",
+                        e
+                    )));
+                }
+            };
+            let mut data = stored_document_data(searcher.schema(), &doc);
+            // NOTE: Only the alive versions of an mvcc index are copied (they keep created_ts,
+            // which is part of the data), the history before the reindex is lost.
+            if source_mvcc
+                && data.get(mvcc::DELETED_TS_FIELD).and_then(Value::as_u64) != Some(u64::MAX)
+            {
+                continue;
+            }
+            transform.apply(&mut data);
+            let input = ffi::DocumentInput {
+                data: Value::Object(data).to_string(),
+                timestamp: 0,
+            };
+            add_document(target, &input, true)?;
+            state.processed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    commit(target)?;
+    Ok(())
+}
+
+pub fn reindex(
+    source: &mut ffi::Context,
+    target_path: &String,
+    config: &ffi::IndexConfig,
+    transform: &str,
+) -> Result<Box<ReindexHandle>, std::io::Error> {
+    let transform = ReindexTransform::parse(transform)?;
+    let source_schema = source.tantivyContext.schema.clone();
+    let unstored: Vec<&str> = source_schema
+        .fields()
+        .map(|(_, field_entry)| field_entry)
+        .filter(|field_entry| {
+            (field_entry.is_indexed() || field_entry.is_fast()) && !field_entry.is_stored()
+        })
+        .map(|field_entry| field_entry.name())
+        .filter(|name| !transform.remove.iter().any(|removed| removed == name))
+        .collect();
+    if !unstored.is_empty() {
+        return Err(Error::other(format!(
+            "Unable to reindex {:?} text search index because fields {:?} are not stored (remove them with the transform to drop them)",
+            source.tantivyContext.index_path, unstored
+        )));
+    }
+    let mut target = open_index(target_path, config, &source.tantivyContext.resources)?;
+    // NOTE: The searcher is a snapshot -> changes to the source made after this point are not
+    // copied.
+    let searcher = source.tantivyContext.index_reader.searcher();
+    let source_mvcc = source.tantivyContext.settings.mvcc;
+    let state = Arc::new(ReindexState {
+        total: mvcc::num_docs(
+            &searcher,
+            &source.tantivyContext.settings,
+            &source.tantivyContext.index_path,
+        )?,
+        ..Default::default()
+    });
+    let thread_state = state.clone();
+    let thread = std::thread::Builder::new()
+        .name("mgcxx-reindex".to_string())
+        .spawn(move || {
+            let res = reindex_documents(
+                &searcher,
+                source_mvcc,
+                &mut target,
+                &transform,
+                &thread_state,
+            );
+            let target = match res {
+                Ok(_) => Some(target),
+                Err(e) => {
+                    if let Ok(mut error) = thread_state.error.lock() {
+                        *error = Some(e.to_string());
+                    }
+                    None
+                }
+            };
+            thread_state.done.store(true, Ordering::Release);
+            target
+        });
+    let thread = match thread {
+        Ok(t) => t,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to start the reindex thread -> {}",
+                e
+            )));
+        }
+    };
+    Ok(Box::new(ReindexHandle {
+        state,
+        thread: Some(thread),
+    }))
+}
+
+// NOTE: The handle comes from C++ as a Box.
+#[allow(clippy::boxed_local)]
+pub fn switch_to_reindex_target(
+    mut handle: Box<ReindexHandle>,
+    source: &mut ffi::Context,
+) -> Result<ffi::Context, std::io::Error> {
+    // NOTE: Dropping the handle stops the reindex.
+    managed_context_check(source, "switched to the reindex target")?;
+    let target = match handle.thread.take().map(|t| t.join()) {
+        Some(Ok(Some(target))) => target,
+        Some(Ok(None)) => {
+            return Err(Error::other(format!(
+                "Reindex of {:?} text search index failed -> {}",
+                source.tantivyContext.index_path,
+                handle.progress().error
+            )));
+        }
+        Some(Err(_)) | None => {
+            return Err(Error::other(format!(
+                "Reindex of {:?} text search index failed unexpectedly",
+                source.tantivyContext.index_path
+            )));
+        }
+    };
+    Ok(std::mem::replace(source, target))
+}
@@ -0,0 +1,75 @@
+//! Differences between the schema of an existing index and the schema created from mappings.
+
+use std::fmt;
+use tantivy::schema::{FieldEntry, Schema};
+
+#[derive(Debug)]
+pub enum SchemaChange {
+    Added(FieldEntry),
+    Removed(String),
+    Changed { old: FieldEntry, new: FieldEntry },
+}
+
+impl SchemaChange {
+    /// Only new fields can be introduced without reindexing (old documents just miss them).
+    pub fn is_compatible(&self) -> bool {
+        matches!(self, SchemaChange::Added(_))
+    }
+}
+
+fn field_entry_to_string(entry: &FieldEntry) -> String {
+    match serde_json::to_string(entry) {
+        Ok(s) => s,
+        Err(_) => format!("{:?}", entry),
+    }
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaChange::Added(entry) => write!(f, "field '{}' added", entry.name()),
+            SchemaChange::Removed(name) => write!(f, "field '{}' removed", name),
+            SchemaChange::Changed { old, new } => write!(
+                f,
+                "field '{}' changed from {} to {}",
+                old.name(),
+                field_entry_to_string(old),
+                field_entry_to_string(new)
+            ),
+        }
+    }
+}
+
+/// Fields are matched by name (field order doesn't matter). New fields are returned in the order
+/// of the new schema.
+pub fn schema_changes(old: &Schema, new: &Schema) -> Vec<SchemaChange> {
+    let mut changes = Vec::new();
+    for (_, old_entry) in old.fields() {
+        match new.get_field(old_entry.name()) {
+            Ok(field) => {
+                let new_entry = new.get_field_entry(field);
+                if new_entry != old_entry {
+                    changes.push(SchemaChange::Changed {
+                        old: old_entry.clone(),
+                        new: new_entry.clone(),
+                    });
+                }
+            }
+            Err(_) => changes.push(SchemaChange::Removed(old_entry.name().to_string())),
+        }
+    }
+    for (_, new_entry) in new.fields() {
+        if old.get_field(new_entry.name()).is_err() {
+            changes.push(SchemaChange::Added(new_entry.clone()));
+        }
+    }
+    changes
+}
+
+pub fn describe(changes: &[SchemaChange]) -> String {
+    changes
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>()
+        .join("; ")
+}
@@ -0,0 +1,464 @@
+//! Custom scoring of the search results: BM25 with index specific parameters (the term queries
+//! are replaced while the query is parsed) and score tweaks combining the text score with a fast
+//! field value.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Error;
+use tantivy::collector::{ScoreSegmentTweaker, ScoreTweaker};
+use tantivy::columnar::DynamicColumn;
+use tantivy::fieldnorm::FieldNormReader;
+use tantivy::postings::SegmentPostings;
+use tantivy::query::{
+    BooleanQuery, BoostQuery, EmptyQuery, EmptyScorer, EnableScoring, Explanation, Occur,
+    PhrasePrefixQuery, PhraseQuery, Query, QueryParser, QueryParserError, RangeQuery, Scorer,
+    TermQuery, Weight,
+};
+use tantivy::query_grammar::{self, UserInputAst, UserInputLeaf};
+use tantivy::schema::{Field, IndexRecordOption, Schema};
+use tantivy::{DocId, DocSet, Postings, Score, Searcher, SegmentReader, TantivyError, Term};
+
+use crate::{search_fast_field_value, search_open_fast_field_columns};
+
+fn default_bm25_k1() -> f32 {
+    1.2
+}
+
+fn default_bm25_b() -> f32 {
+    0.75
+}
+
+/// BM25 parameters, tantivy defaults are used for the missing ones.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bm25Settings {
+    #[serde(default = "default_bm25_k1")]
+    pub k1: f32,
+    #[serde(default = "default_bm25_b")]
+    pub b: f32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreTweakFunction {
+    /// score * factor * value
+    Multiply,
+    /// score * ln(1 + factor * value)
+    Log1p,
+    /// score * 0.5 ^ (|value - origin| / scale), the score halves every scale away from origin.
+    Decay,
+}
+
+fn default_score_tweak_factor() -> f64 {
+    1.0
+}
+
+fn default_score_tweak_scale() -> f64 {
+    1.0
+}
+
+/// Parsed [crate::ffi::SearchInput::score_tweak].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScoreTweak {
+    field: String,
+    function: ScoreTweakFunction,
+    #[serde(default = "default_score_tweak_factor")]
+    factor: f64,
+    #[serde(default)]
+    origin: f64,
+    #[serde(default = "default_score_tweak_scale")]
+    scale: f64,
+    /// Value used for documents without the field, if not set, their score stays unchanged.
+    #[serde(default)]
+    missing: Option<f64>,
+}
+
+impl ScoreTweak {
+    pub fn parse(score_tweak: &str) -> Result<Option<ScoreTweak>, Error> {
+        if score_tweak.is_empty() {
+            return Ok(None);
+        }
+        let tweak: ScoreTweak = match serde_json::from_str(score_tweak) {
+            Ok(t) => t,
+            Err(e) => {
+                return Err(Error::other(format!(
+                    "Unable to parse score tweak -> {}",
+                    e
+                )));
+            }
+        };
+        if tweak.scale <= 0.0 {
+            return Err(Error::other("score tweak -> scale has to be positive"));
+        }
+        Ok(Some(tweak))
+    }
+
+    fn apply(&self, score: Score, value: Option<f64>) -> Score {
+        let value = match value.or(self.missing) {
+            Some(v) => v,
+            None => return score,
+        };
+        let multiplier = match self.function {
+            ScoreTweakFunction::Multiply => self.factor * value,
+            ScoreTweakFunction::Log1p => (self.factor * value).ln_1p(),
+            ScoreTweakFunction::Decay => 0.5f64.powf((value - self.origin).abs() / self.scale),
+        };
+        (score as f64 * multiplier) as Score
+    }
+}
+
+/// Computes the final score of each hit. Created only if the search has a score tweak, otherwise
+/// the query scores are used as they are.
+pub struct SearchScorer {
+    tweak: ScoreTweak,
+}
+
+impl SearchScorer {
+    pub fn new(
+        searcher: &Searcher,
+        tweak: Option<ScoreTweak>,
+    ) -> tantivy::Result<Option<SearchScorer>> {
+        let tweak = match tweak {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let field = match searcher.schema().find_field(&tweak.field) {
+            Some((field, _)) => field,
+            None => return Err(TantivyError::FieldNotFound(tweak.field.clone())),
+        };
+        if !searcher.schema().get_field_entry(field).is_fast() {
+            return Err(TantivyError::SchemaError(format!(
+                "score tweak field '{}' has to be fast",
+                tweak.field
+            )));
+        }
+        Ok(Some(SearchScorer { tweak }))
+    }
+}
+
+pub struct SegmentSearchScorer {
+    tweak: ScoreTweak,
+    columns: Vec<DynamicColumn>,
+}
+
+impl ScoreTweaker<Score> for SearchScorer {
+    type Child = SegmentSearchScorer;
+
+    fn segment_tweaker(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(SegmentSearchScorer {
+            tweak: self.tweak.clone(),
+            columns: search_open_fast_field_columns(segment_reader, &self.tweak.field)?,
+        })
+    }
+}
+
+impl ScoreSegmentTweaker<Score> for SegmentSearchScorer {
+    fn score(&mut self, doc: DocId, score: Score) -> Score {
+        let mut value = None;
+        for column in &self.columns {
+            // NOTE: The error can only come from reading string columns, which are not numeric
+            // anyway.
+            if let Ok(Some(v)) = search_fast_field_value(column, doc) {
+                value = v.as_f64().or(v.as_bool().map(|b| b as u8 as f64));
+                if value.is_some() {
+                    break;
+                }
+            }
+        }
+        self.tweak.apply(score, value)
+    }
+}
+
+/// Parses the query the same way the given query parser does, except that the term queries are
+/// scored with the custom BM25 parameters. The parser must not have any field boosts, field_boosts
+/// are applied here instead (tantivy boost queries can't be looked into, so the boosts written in
+/// the query are applied here as well).
+/// NOTE: Phrase queries (also the implicit ones, e.g. a term the tokenizer splits into multiple
+/// tokens) are rejected, tantivy doesn't allow scoring them with other BM25 parameters. The other
+/// queries (e.g. range or regex) keep their constant scores.
+pub fn parse_bm25_query(
+    query_parser: &QueryParser,
+    schema: &Schema,
+    field_boosts: &HashMap<Field, Score>,
+    settings: &Bm25Settings,
+    query: &str,
+    conjunction_by_default: bool,
+    lenient: bool,
+) -> Result<(Box<dyn Query>, Vec<QueryParserError>), QueryParserError> {
+    let (mut ast, mut errors) = if lenient {
+        let (ast, errors) = query_grammar::parse_query_lenient(query);
+        let errors = errors
+            .into_iter()
+            .map(|e| QueryParserError::SyntaxError(format!("{} at position {}", e.message, e.pos)))
+            .collect();
+        (ast, errors)
+    } else {
+        let ast = query_grammar::parse_query(query)
+            .map_err(|_| QueryParserError::SyntaxError(query.to_string()))?;
+        (ast, Vec::new())
+    };
+    let builder = Bm25QueryBuilder {
+        query_parser,
+        schema,
+        field_boosts,
+        settings,
+        default_occur: if conjunction_by_default {
+            Occur::Must
+        } else {
+            Occur::Should
+        },
+    };
+    // NOTE: Same as the query parser, a query excluding documents matches all the other ones.
+    let is_empty = matches!(&ast, UserInputAst::Clause(c) if c.is_empty());
+    if !is_empty && builder.all_negative(&ast) {
+        errors.push(QueryParserError::AllButQueryForbidden);
+        make_non_negative(&mut ast);
+    }
+    let query = builder.build(ast, &mut errors)?;
+    if !lenient && !errors.is_empty() {
+        return Err(errors.swap_remove(0));
+    }
+    let query = query.unwrap_or_else(|| Box::new(EmptyQuery));
+    Ok((query, errors))
+}
+
+fn make_non_negative(ast: &mut UserInputAst) {
+    match ast {
+        UserInputAst::Leaf(_) => {}
+        UserInputAst::Boost(child, _) => make_non_negative(child),
+        UserInputAst::Clause(children) => children.push((
+            Some(Occur::Should),
+            UserInputAst::Leaf(Box::new(UserInputLeaf::All)),
+        )),
+    }
+}
+
+struct Bm25QueryBuilder<'a> {
+    query_parser: &'a QueryParser,
+    schema: &'a Schema,
+    field_boosts: &'a HashMap<Field, Score>,
+    settings: &'a Bm25Settings,
+    default_occur: Occur,
+}
+
+impl Bm25QueryBuilder<'_> {
+    fn all_negative(&self, ast: &UserInputAst) -> bool {
+        match ast {
+            UserInputAst::Leaf(_) => false,
+            UserInputAst::Boost(child, _) => self.all_negative(child),
+            UserInputAst::Clause(children) => children.iter().all(|(occur, child)| {
+                occur.unwrap_or(self.default_occur) == Occur::MustNot || self.all_negative(child)
+            }),
+        }
+    }
+
+    /// Returns None if nothing is left of the query (e.g. in the lenient mode). Queries which
+    /// can't be scored with the custom parameters are an error in both modes.
+    fn build(
+        &self,
+        ast: UserInputAst,
+        errors: &mut Vec<QueryParserError>,
+    ) -> Result<Option<Box<dyn Query>>, QueryParserError> {
+        match ast {
+            UserInputAst::Clause(children) => {
+                let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+                for (occur, child) in children {
+                    if let Some(query) = self.build(child, errors)? {
+                        clauses.push((occur.unwrap_or(self.default_occur), query));
+                    }
+                }
+                if clauses.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(Box::new(BooleanQuery::new(clauses))))
+                }
+            }
+            UserInputAst::Boost(child, boost) => Ok(self
+                .build(*child, errors)?
+                .map(|query| Box::new(BoostQuery::new(query, boost as Score)) as Box<dyn Query>)),
+            UserInputAst::Leaf(leaf) => {
+                let (query, mut leaf_errors) = self
+                    .query_parser
+                    .build_query_from_user_input_ast_lenient(UserInputAst::Leaf(leaf));
+                errors.append(&mut leaf_errors);
+                if query.is::<EmptyQuery>() {
+                    Ok(None)
+                } else {
+                    self.rewrite(query).map(Some)
+                }
+            }
+        }
+    }
+
+    /// Replaces the term queries and applies the field boosts to the query of a single leaf.
+    fn rewrite(&self, query: Box<dyn Query>) -> Result<Box<dyn Query>, QueryParserError> {
+        if let Some(boolean_query) = query.downcast_ref::<BooleanQuery>() {
+            let clauses = boolean_query
+                .clauses()
+                .iter()
+                .map(|(occur, clause)| Ok((*occur, self.rewrite(clause.box_clone())?)))
+                .collect::<Result<_, QueryParserError>>()?;
+            return Ok(Box::new(BooleanQuery::new(clauses)));
+        }
+        if query.is::<PhraseQuery>() || query.is::<PhrasePrefixQuery>() {
+            return Err(QueryParserError::UnsupportedQuery(
+                "phrase queries can't be scored with the custom bm25 parameters".to_string(),
+            ));
+        }
+        let (query, field): (Box<dyn Query>, Option<Field>) =
+            if let Some(term_query) = query.downcast_ref::<TermQuery>() {
+                let term = term_query.term().clone();
+                let field = term.field();
+                let bm25_query = Bm25TermQuery {
+                    term,
+                    settings: self.settings.clone(),
+                };
+                (Box::new(bm25_query), Some(field))
+            } else if let Some(range_query) = query.downcast_ref::<RangeQuery>() {
+                let field = self.schema.get_field(range_query.field()).ok();
+                (query, field)
+            } else {
+                let mut field = None;
+                query.query_terms(&mut |term, _| {
+                    field.get_or_insert(term.field());
+                });
+                (query, field)
+            };
+        match field.and_then(|f| self.field_boosts.get(&f)) {
+            Some(boost) if *boost != 1.0 => Ok(Box::new(BoostQuery::new(query, *boost))),
+            _ => Ok(query),
+        }
+    }
+}
+
+/// Term query scored by BM25 with the index specific parameters.
+#[derive(Clone, Debug)]
+struct Bm25TermQuery {
+    term: Term,
+    settings: Bm25Settings,
+}
+
+impl Query for Bm25TermQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        let statistics = match enable_scoring {
+            EnableScoring::Enabled {
+                statistics_provider,
+                ..
+            } => statistics_provider,
+            EnableScoring::Disabled { .. } => {
+                return TermQuery::new(self.term.clone(), IndexRecordOption::Basic)
+                    .weight(enable_scoring);
+            }
+        };
+        let schema = enable_scoring.schema();
+        let field_entry = schema.get_field_entry(self.term.field());
+        if !field_entry.is_indexed() {
+            return Err(TantivyError::SchemaError(format!(
+                "Field {:?} is not indexed.",
+                field_entry.name()
+            )));
+        }
+        let total_num_docs = statistics.total_num_docs()?;
+        let total_num_tokens = statistics.total_num_tokens(self.term.field())?;
+        let average_fieldnorm = total_num_tokens as Score / total_num_docs as Score;
+        let idf = bm25_idf(statistics.doc_freq(&self.term)?, total_num_docs);
+        let mut norms = [0.0; 256];
+        for (fieldnorm_id, norm) in norms.iter_mut().enumerate() {
+            let fieldnorm = FieldNormReader::id_to_fieldnorm(fieldnorm_id as u8) as Score;
+            *norm = self.settings.k1
+                * (1.0 - self.settings.b + self.settings.b * fieldnorm / average_fieldnorm);
+        }
+        Ok(Box::new(Bm25TermWeight {
+            term: self.term.clone(),
+            weight: idf * (1.0 + self.settings.k1),
+            norms,
+        }))
+    }
+
+    fn query_terms<'a>(&'a self, visitor: &mut dyn FnMut(&'a Term, bool)) {
+        visitor(&self.term, false);
+    }
+}
+
+fn bm25_idf(doc_freq: u64, doc_count: u64) -> Score {
+    let x = (doc_count.saturating_sub(doc_freq) as Score + 0.5) / (doc_freq as Score + 0.5);
+    (1.0 + x).ln()
+}
+
+struct Bm25TermWeight {
+    term: Term,
+    /// idf * (k1 + 1)
+    weight: Score,
+    /// k1 * (1 - b + b * fieldnorm / average_fieldnorm) of each fieldnorm id.
+    norms: [Score; 256],
+}
+
+impl Weight for Bm25TermWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let field = self.term.field();
+        let postings = reader
+            .inverted_index(field)?
+            .read_postings(&self.term, IndexRecordOption::WithFreqs)?;
+        let postings = match postings {
+            Some(p) => p,
+            None => return Ok(Box::new(EmptyScorer)),
+        };
+        let fieldnorm_reader = reader
+            .fieldnorms_readers()
+            .get_field(field)?
+            .unwrap_or_else(|| FieldNormReader::constant(reader.max_doc(), 1));
+        Ok(Box::new(Bm25TermScorer {
+            postings,
+            fieldnorm_reader,
+            weight: self.weight * boost,
+            norms: self.norms,
+        }))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.doc() > doc || scorer.seek(doc) != doc {
+            return Err(TantivyError::InvalidArgument(format!(
+                "Document #({}) does not match",
+                doc
+            )));
+        }
+        let mut explanation = Explanation::new("BM25 with custom k1 and b", scorer.score());
+        explanation.add_context(format!("Term={:?}", self.term));
+        Ok(explanation)
+    }
+}
+
+struct Bm25TermScorer {
+    postings: SegmentPostings,
+    fieldnorm_reader: FieldNormReader,
+    weight: Score,
+    norms: [Score; 256],
+}
+
+impl DocSet for Bm25TermScorer {
+    fn advance(&mut self) -> DocId {
+        self.postings.advance()
+    }
+
+    fn seek(&mut self, target: DocId) -> DocId {
+        self.postings.seek(target)
+    }
+
+    fn doc(&self) -> DocId {
+        self.postings.doc()
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.postings.size_hint()
+    }
+}
+
+impl Scorer for Bm25TermScorer {
+    fn score(&mut self) -> Score {
+        let term_freq = self.postings.term_freq() as Score;
+        let fieldnorm_id = self.fieldnorm_reader.fieldnorm_id(self.doc());
+        self.weight * term_freq / (term_freq + self.norms[fieldnorm_id as usize])
+    }
+}
@@ -0,0 +1,118 @@
+//! Searchers pinned to a single index state (generation) for repeatable reads.
+
+use tantivy::Searcher;
+
+use crate::{
+    ffi, metrics, mvcc, searcher_aggregate, searcher_count, searcher_regex_search, searcher_search,
+    CancellationToken, IndexSettings, Interruption,
+};
+
+pub struct SearcherHandle {
+    searcher: Searcher,
+    settings: IndexSettings,
+    index_path: std::path::PathBuf,
+}
+
+pub fn acquire_searcher(context: &mut ffi::Context) -> Result<Box<SearcherHandle>, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    Ok(Box::new(SearcherHandle {
+        searcher: tantivy_context.index_reader.searcher(),
+        settings: tantivy_context.settings.clone(),
+        index_path: tantivy_context.index_path.clone(),
+    }))
+}
+
+// NOTE: The handle comes from C++ as a Box.
+#[allow(clippy::boxed_local)]
+pub fn release_searcher(handle: Box<SearcherHandle>) {
+    drop(handle);
+}
+
+impl SearcherHandle {
+    pub fn generation(&self) -> u64 {
+        self.searcher.generation().generation_id()
+    }
+
+    /// Runs one of the searcher_* functions against the pinned searcher.
+    fn pinned_search<T>(
+        &self,
+        input: &ffi::SearchInput,
+        token: Option<&CancellationToken>,
+        searcher_fn: impl FnOnce(
+            &Searcher,
+            &IndexSettings,
+            &std::path::PathBuf,
+            &ffi::SearchInput,
+            &Interruption,
+        ) -> Result<T, std::io::Error>,
+    ) -> Result<T, std::io::Error> {
+        let interruption = Interruption::new(input, token);
+        metrics::measure_search(|| {
+            searcher_fn(
+                &self.searcher,
+                &self.settings,
+                &self.index_path,
+                input,
+                &interruption,
+            )
+        })
+    }
+
+    pub fn search(&self, input: &ffi::SearchInput) -> Result<ffi::SearchOutput, std::io::Error> {
+        self.pinned_search(input, None, searcher_search)
+    }
+
+    pub fn search_cancellable(
+        &self,
+        input: &ffi::SearchInput,
+        token: &CancellationToken,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        self.pinned_search(input, Some(token), searcher_search)
+    }
+
+    pub fn regex_search(
+        &self,
+        input: &ffi::SearchInput,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        self.pinned_search(input, None, searcher_regex_search)
+    }
+
+    pub fn regex_search_cancellable(
+        &self,
+        input: &ffi::SearchInput,
+        token: &CancellationToken,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        self.pinned_search(input, Some(token), searcher_regex_search)
+    }
+
+    pub fn aggregate(
+        &self,
+        input: &ffi::SearchInput,
+    ) -> Result<ffi::DocumentOutput, std::io::Error> {
+        self.pinned_search(input, None, searcher_aggregate)
+    }
+
+    pub fn aggregate_cancellable(
+        &self,
+        input: &ffi::SearchInput,
+        token: &CancellationToken,
+    ) -> Result<ffi::DocumentOutput, std::io::Error> {
+        self.pinned_search(input, Some(token), searcher_aggregate)
+    }
+
+    pub fn count(&self, input: &ffi::SearchInput) -> Result<u64, std::io::Error> {
+        self.pinned_search(input, None, searcher_count)
+    }
+
+    pub fn count_cancellable(
+        &self,
+        input: &ffi::SearchInput,
+        token: &CancellationToken,
+    ) -> Result<u64, std::io::Error> {
+        self.pinned_search(input, Some(token), searcher_count)
+    }
+
+    pub fn get_num_docs(&self) -> Result<u64, std::io::Error> {
+        mvcc::num_docs(&self.searcher, &self.settings, &self.index_path)
+    }
+}
@@ -0,0 +1,368 @@
+//! Per transaction overlay of uncommitted changes, so a transaction can search its own writes.
+
+use std::io::Error;
+use tantivy::collector::Count;
+use tantivy::query::Query;
+use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, Searcher, TantivyDocument};
+
+use crate::{
+    cancellation, commit_writer, delete_parse_query, ffi, finish_operation, metrics, mvcc,
+    search_exclude, search_merge_docs, search_parse_query, search_retrieve_docs, search_top_docs,
+    wal, write_document, CancellationToken, IndexSettings, Interruption,
+};
+
+enum PendingOperation {
+    /// JSON encoded document and its timestamp (take a look under mvcc).
+    Add(String, u64),
+    /// The timestamp is there only for delete_document_at (take a look under mvcc).
+    Delete(Box<dyn Query>, wal::WalDeleteQuery, Option<u64>),
+}
+
+/// In-memory index holding the documents added by the transaction.
+struct OverlayIndex {
+    writer: IndexWriter,
+    reader: IndexReader,
+    /// The writer has changes the reader doesn't see yet.
+    dirty: bool,
+}
+
+pub struct Transaction {
+    index: Index,
+    index_reader: IndexReader,
+    settings: IndexSettings,
+    index_path: std::path::PathBuf,
+    /// Created on the first add (read-only transactions don't need it).
+    overlay: Option<OverlayIndex>,
+    /// All changes in the order they were made, replayed on commit.
+    operations: Vec<PendingOperation>,
+}
+
+pub fn begin_transaction(context: &mut ffi::Context) -> Result<Box<Transaction>, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    Ok(Box::new(Transaction {
+        index: tantivy_context.index.clone(),
+        index_reader: tantivy_context.index_reader.clone(),
+        settings: tantivy_context.settings.clone(),
+        index_path: tantivy_context.index_path.clone(),
+        overlay: None,
+        operations: Vec::new(),
+    }))
+}
+
+// NOTE: The transaction comes from C++ as a Box.
+#[allow(clippy::boxed_local)]
+pub fn commit_transaction(
+    transaction: Box<Transaction>,
+    context: &mut ffi::Context,
+    skip_commit: bool,
+) -> Result<u64, std::io::Error> {
+    let tantivy_context = &context.tantivyContext;
+    let index_path = &tantivy_context.index_path;
+    // NOTE: The writer stays locked during the replay -> the auto-commit can't commit just a part
+    // of the transaction.
+    let mut writer = tantivy_context.writer.lock(index_path)?;
+    // NOTE: Operations are replayed in order -> a delete affects only the documents added before
+    // it (same as in the overlay).
+    for operation in transaction.operations {
+        match operation {
+            PendingOperation::Add(data, timestamp) => {
+                let input = ffi::DocumentInput { data, timestamp };
+                write_document(tantivy_context, &mut writer, &input, true)?;
+            }
+            PendingOperation::Delete(query, wal_query, Some(timestamp)) => {
+                mvcc::delete_query_at(
+                    tantivy_context,
+                    &mut writer,
+                    query,
+                    wal_query,
+                    timestamp,
+                    true,
+                )?;
+            }
+            PendingOperation::Delete(query, wal_query, None) => {
+                let opstamp = match writer.index_writer.delete_query(query) {
+                    Ok(o) => o,
+                    Err(e) => {
+                        return Err(Error::other(format!(
+                            "Unable to delete document from text search index at {:?} -> {}",
+                            index_path, e
+                        )));
+                    }
+                };
+                metrics::inc(&metrics::METRICS.delete_queries);
+                finish_operation(tantivy_context, &mut writer, opstamp, true, || {
+                    wal::WalOperation::Delete { query: wal_query }
+                })?;
+            }
+        }
+    }
+    if skip_commit {
+        Ok(writer.index_writer.commit_opstamp())
+    } else {
+        commit_writer(&mut writer, &tantivy_context.index_reader, index_path)
+    }
+}
+
+// NOTE: The transaction comes from C++ as a Box.
+#[allow(clippy::boxed_local)]
+pub fn rollback_transaction(transaction: Box<Transaction>) {
+    drop(transaction);
+}
+
+impl Transaction {
+    fn overlay(&mut self) -> Result<&mut OverlayIndex, std::io::Error> {
+        if self.overlay.is_none() {
+            let index = Index::create_in_ram(self.index.schema());
+            let writer = match index.writer_with_num_threads(1, 15_000_000) {
+                Ok(w) => w,
+                Err(e) => {
+                    return Err(Error::other(format!(
+                        "Unable to create transaction overlay of {:?} text search index -> {}",
+                        self.index_path, e
+                    )));
+                }
+            };
+            let reader = match index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::Manual)
+                .try_into()
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    return Err(Error::other(format!(
+                        "Unable to create transaction overlay reader of {:?} text search index -> {}",
+                        self.index_path, e
+                    )));
+                }
+            };
+            self.overlay = Some(OverlayIndex {
+                writer,
+                reader,
+                dirty: false,
+            });
+        }
+        match self.overlay.as_mut() {
+            Some(o) => Ok(o),
+            None => Err(Error::other("transaction overlay is missing")),
+        }
+    }
+
+    /// Makes all the changes visible to the overlay searcher.
+    fn overlay_searcher(&mut self) -> Result<Option<Searcher>, std::io::Error> {
+        let overlay = match self.overlay.as_mut() {
+            Some(o) => o,
+            None => return Ok(None),
+        };
+        if overlay.dirty {
+            if let Err(e) = overlay.writer.commit() {
+                return Err(Error::other(format!(
+                    "Unable to refresh transaction overlay of {:?} text search index -> {}",
+                    self.index_path, e
+                )));
+            }
+            if let Err(e) = overlay.reader.reload() {
+                return Err(Error::other(format!(
+                    "Unable to reload transaction overlay reader of {:?} text search index -> {}",
+                    self.index_path, e
+                )));
+            }
+            overlay.dirty = false;
+        }
+        Ok(Some(overlay.reader.searcher()))
+    }
+
+    /// Delete queries applied to the committed documents, a delete_document_at hides them only
+    /// from the searches at or after its timestamp.
+    fn pending_deletes(&self, as_of_timestamp: u64) -> Vec<Box<dyn Query>> {
+        self.operations
+            .iter()
+            .filter_map(|operation| match operation {
+                PendingOperation::Delete(_, _, Some(timestamp))
+                    if as_of_timestamp != 0 && as_of_timestamp < *timestamp =>
+                {
+                    None
+                }
+                PendingOperation::Delete(query, ..) => Some(query.box_clone()),
+                PendingOperation::Add(..) => None,
+            })
+            .collect()
+    }
+
+    pub fn add_document(&mut self, input: &ffi::DocumentInput) -> Result<(), std::io::Error> {
+        let schema = self.index.schema();
+        let mut document = match TantivyDocument::parse_json(&schema, &input.data) {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(Error::other(format!(
+                    "Unable to add document into text search index {:?} because schema doesn't match -> {} Please check mappings.",
+                    self.index_path, e
+                )));
+            }
+        };
+        mvcc::stamp_document(&schema, &self.settings, &mut document, input.timestamp);
+        let overlay = self.overlay()?;
+        if let Err(e) = overlay.writer.add_document(document) {
+            return Err(Error::other(format!("Unable to add document -> {}", e)));
+        }
+        overlay.dirty = true;
+        self.operations.push(PendingOperation::Add(
+            input.data.to_string(),
+            input.timestamp,
+        ));
+        Ok(())
+    }
+
+    pub fn delete_document(&mut self, input: &ffi::SearchInput) -> Result<(), std::io::Error> {
+        mvcc::physical_delete_check(&self.settings, &self.index_path)?;
+        self.delete(input, None)
+    }
+
+    pub fn delete_document_at(
+        &mut self,
+        input: &ffi::SearchInput,
+        timestamp: u64,
+    ) -> Result<(), std::io::Error> {
+        mvcc::mvcc_settings_check(&self.settings, &self.index_path)?;
+        self.delete(input, Some(timestamp))
+    }
+
+    /// NOTE: The own adds are removed right away, they were never visible to other transactions.
+    fn delete(
+        &mut self,
+        input: &ffi::SearchInput,
+        timestamp: Option<u64>,
+    ) -> Result<(), std::io::Error> {
+        let query = delete_parse_query(&self.index, input, &self.index_path)?;
+        if let Some(overlay) = self.overlay.as_mut() {
+            if let Err(e) = overlay.writer.delete_query(query.box_clone()) {
+                return Err(Error::other(format!(
+                    "Unable to delete document from transaction overlay of {:?} text search index -> {}",
+                    self.index_path, e
+                )));
+            }
+            overlay.dirty = true;
+        }
+        self.operations.push(PendingOperation::Delete(
+            query,
+            wal::WalDeleteQuery::new(input),
+            timestamp,
+        ));
+        Ok(())
+    }
+
+    pub fn search(
+        &mut self,
+        input: &ffi::SearchInput,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        self.search_interruptible(input, None)
+    }
+
+    pub fn search_cancellable(
+        &mut self,
+        input: &ffi::SearchInput,
+        token: &CancellationToken,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        self.search_interruptible(input, Some(token))
+    }
+
+    fn search_interruptible(
+        &mut self,
+        input: &ffi::SearchInput,
+        token: Option<&CancellationToken>,
+    ) -> Result<ffi::SearchOutput, std::io::Error> {
+        let interruption = Interruption::new(input, token);
+        metrics::measure_search(|| {
+            let (query, mut warnings) = search_parse_query(
+                &self.index,
+                input,
+                self.settings.bm25.as_ref(),
+                &self.index_path,
+            )?;
+            let searcher = self.index_reader.searcher();
+            let (top_docs, mut total_count) = search_top_docs(
+                &searcher,
+                query.as_ref(),
+                &self.pending_deletes(input.as_of_timestamp),
+                input,
+                &self.settings,
+                &self.index_path,
+                &interruption,
+            )?;
+            let mut docs = search_retrieve_docs(&searcher, top_docs, input, &self.index_path)?;
+            if let Some(overlay_searcher) = self.overlay_searcher()? {
+                let (top_docs, count) = search_top_docs(
+                    &overlay_searcher,
+                    query.as_ref(),
+                    &[],
+                    input,
+                    &self.settings,
+                    &self.index_path,
+                    &interruption,
+                )?;
+                total_count += count;
+                docs.extend(search_retrieve_docs(
+                    &overlay_searcher,
+                    top_docs,
+                    input,
+                    &self.index_path,
+                )?);
+            }
+            warnings.extend(interruption.check(input, &self.index_path)?);
+            search_merge_docs(&mut docs, input.effective_limit());
+            Ok(ffi::SearchOutput {
+                docs,
+                total_count,
+                warnings,
+            })
+        })
+    }
+
+    pub fn count(&mut self, input: &ffi::SearchInput) -> Result<u64, std::io::Error> {
+        self.count_interruptible(input, None)
+    }
+
+    pub fn count_cancellable(
+        &mut self,
+        input: &ffi::SearchInput,
+        token: &CancellationToken,
+    ) -> Result<u64, std::io::Error> {
+        self.count_interruptible(input, Some(token))
+    }
+
+    fn count_interruptible(
+        &mut self,
+        input: &ffi::SearchInput,
+        token: Option<&CancellationToken>,
+    ) -> Result<u64, std::io::Error> {
+        let interruption = Interruption::new(input, token);
+        metrics::measure_search(|| {
+            let (query, _) = search_parse_query(&self.index, input, None, &self.index_path)?;
+            let mut searchers = vec![(
+                self.index_reader.searcher(),
+                self.pending_deletes(input.as_of_timestamp),
+            )];
+            if let Some(overlay_searcher) = self.overlay_searcher()? {
+                searchers.push((overlay_searcher, Vec::new()));
+            }
+            let mut total_count = 0;
+            for (searcher, excluded) in searchers {
+                let query = mvcc::visible_query(
+                    &self.settings,
+                    search_exclude(query.box_clone(), &excluded),
+                    input.as_of_timestamp,
+                );
+                match cancellation::search(&searcher, query.as_ref(), &Count, &interruption) {
+                    Ok(count) => total_count += count as u64,
+                    Err(e) => {
+                        return Err(Error::other(format!(
+                            "Unable to count matching documents under {:?} -> {}",
+                            self.index_path, e
+                        )));
+                    }
+                }
+            }
+            interruption.check(input, &self.index_path)?;
+            Ok(total_count)
+        })
+    }
+}
@@ -0,0 +1,315 @@
+//! Append-only log of the uncommitted operations (enabled by the wal setting). The log is
+//! cleared on each commit/rollback and replayed when the index is opened again after a crash.
+//!
+//! Each cleared log starts a new (higher) generation (the first line of the log). Commits store
+//! the generation of the log they include as the commit payload, so a log which was committed
+//! but not cleared (crash in between) is not replayed again.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Error, Write};
+
+use crate::{
+    add_document, commit_writer_with_payload, delete_all_documents, delete_document, ffi, mvcc,
+    IndexWriterState,
+};
+
+const WAL_FILE_NAME: &str = "mgcxx_wal.jsonl";
+
+fn default_sync_every() -> u64 {
+    1
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WalSettings {
+    /// fsync after every sync_every logged operations, 0 means never (left to the OS).
+    #[serde(default = "default_sync_every")]
+    sync_every: u64,
+}
+
+/// Inputs of a delete query which are relevant for the set of deleted documents.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalDeleteQuery {
+    search_fields: Vec<String>,
+    search_query: String,
+    conjunction_by_default: bool,
+}
+
+impl WalDeleteQuery {
+    pub fn new(input: &ffi::SearchInput) -> WalDeleteQuery {
+        WalDeleteQuery {
+            search_fields: input.search_fields.clone(),
+            search_query: input.search_query.clone(),
+            conjunction_by_default: input.conjunction_by_default,
+        }
+    }
+
+    fn search_input(&self) -> ffi::SearchInput {
+        ffi::SearchInput {
+            search_fields: self.search_fields.clone(),
+            search_query: self.search_query.clone(),
+            return_fields: Vec::new(),
+            aggregation_query: String::new(),
+            limit: 0,
+            count_total: false,
+            fast_fields: Vec::new(),
+            field_boosts: Vec::new(),
+            conjunction_by_default: self.conjunction_by_default,
+            lenient: false,
+            score_tweak: String::new(),
+            min_score: 0.0,
+            dedup_field: String::new(),
+            as_of_timestamp: 0,
+            timeout_ms: 0,
+            partial_results: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalOperation {
+    Add {
+        data: String,
+        timestamp: u64,
+    },
+    Delete {
+        query: WalDeleteQuery,
+    },
+    DeleteAll,
+    DeleteAt {
+        query: WalDeleteQuery,
+        timestamp: u64,
+    },
+    PurgeDeleted {
+        timestamp: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WalHeader {
+    generation: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WalEntry {
+    opstamp: u64,
+    #[serde(flatten)]
+    operation: WalOperation,
+}
+
+pub struct Wal {
+    file: File,
+    generation: u64,
+    sync_every: u64,
+    /// Operations written since the last fsync.
+    unsynced: u64,
+}
+
+impl Wal {
+    fn append<T: Serialize>(&mut self, entry: &T) -> Result<(), std::io::Error> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.unsynced += 1;
+        if self.sync_every != 0 && self.unsynced >= self.sync_every {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), std::io::Error> {
+        self.file.set_len(0)?;
+        self.generation += 1;
+        let header = WalHeader {
+            generation: self.generation,
+        };
+        let mut line = serde_json::to_string(&header)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        if self.sync_every != 0 {
+            self.file.sync_data()?;
+        }
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+/// Logs the operation if the index has the wal setting (operation is created only then).
+pub fn log(
+    writer: &mut IndexWriterState,
+    index_path: &std::path::Path,
+    opstamp: u64,
+    operation: impl FnOnce() -> WalOperation,
+) -> Result<(), std::io::Error> {
+    let wal = match writer.wal.as_mut() {
+        Some(w) => w,
+        None => return Ok(()),
+    };
+    let entry = WalEntry {
+        opstamp,
+        operation: operation(),
+    };
+    match wal.append(&entry) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::other(format!(
+            "Unable to write the op log of {:?} text search index (the operation is pending, but it won't survive a crash) -> {}",
+            index_path, e
+        ))),
+    }
+}
+
+/// Generation of the log included in the next commit.
+pub fn commit_payload(writer: &IndexWriterState) -> Option<String> {
+    writer.wal.as_ref().map(|wal| wal.generation.to_string())
+}
+
+/// Called once all the logged operations are committed or rolled back.
+pub fn clear(
+    writer: &mut IndexWriterState,
+    index_path: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    let wal = match writer.wal.as_mut() {
+        Some(w) => w,
+        None => return Ok(()),
+    };
+    match wal.clear() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::other(format!(
+            "Unable to clear the op log of {:?} text search index -> {}",
+            index_path, e
+        ))),
+    }
+}
+
+/// Returns the generation (if the log has one) and the logged operations.
+fn read_entries(path: &std::path::Path) -> Result<(Option<u64>, Vec<WalEntry>), std::io::Error> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((None, Vec::new())),
+        Err(e) => return Err(e),
+    };
+    let lines = BufReader::new(file)
+        .lines()
+        .collect::<Result<Vec<String>, std::io::Error>>()?;
+    let generation = match lines.first() {
+        Some(line) => match serde_json::from_str::<WalHeader>(line) {
+            Ok(header) => Some(header.generation),
+            // NOTE: Only the header could be written partially (there is nothing after it).
+            Err(_) if lines.len() == 1 => return Ok((None, Vec::new())),
+            Err(e) => {
+                return Err(Error::other(format!(
+                    "Header of {:?} is corrupted -> {}",
+                    path, e
+                )));
+            }
+        },
+        None => return Ok((None, Vec::new())),
+    };
+    let mut entries = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate().skip(1) {
+        match serde_json::from_str::<WalEntry>(line) {
+            Ok(entry) => entries.push(entry),
+            // NOTE: The last operation might be written only partially (crash in the middle of
+            // the write), it was never acknowledged.
+            Err(e) if i + 1 == lines.len() => {
+                warn!(
+                    "Skipping the partially written last entry of {:?} -> {}",
+                    path, e
+                );
+            }
+            Err(e) => {
+                return Err(Error::other(format!(
+                    "Entry {} of {:?} is corrupted -> {}",
+                    i + 1,
+                    path,
+                    e
+                )));
+            }
+        }
+    }
+    Ok((generation, entries))
+}
+
+fn replay_entry(context: &mut ffi::Context, entry: WalEntry) -> Result<u64, std::io::Error> {
+    match entry.operation {
+        WalOperation::Add { data, timestamp } => {
+            add_document(context, &ffi::DocumentInput { data, timestamp }, true)
+        }
+        WalOperation::Delete { query } => delete_document(context, &query.search_input(), true),
+        WalOperation::DeleteAll => delete_all_documents(context, true),
+        WalOperation::DeleteAt { query, timestamp } => {
+            mvcc::delete_document_at(context, &query.search_input(), timestamp, true)
+        }
+        WalOperation::PurgeDeleted { timestamp } => mvcc::purge_deleted(context, timestamp, true),
+    }
+}
+
+/// Replays (and commits) the operations which were logged but not committed before the index
+/// was closed, then starts logging.
+pub fn open(context: &mut ffi::Context) -> Result<(), std::io::Error> {
+    let sync_every = match &context.tantivyContext.settings.wal {
+        Some(settings) => settings.sync_every,
+        None => return Ok(()),
+    };
+    let index_path = context.tantivyContext.index_path.clone();
+    let wal_path = index_path.join(WAL_FILE_NAME);
+    let (generation, entries) = match read_entries(&wal_path) {
+        Ok(r) => r,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to read the op log of {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+    };
+    let committed_generation = match context.tantivyContext.index.load_metas() {
+        Ok(metas) => metas.payload.and_then(|p| p.parse::<u64>().ok()),
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to read the last commit of {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+    };
+    // NOTE: The log might be already committed if the process crashed before it was cleared.
+    if generation > committed_generation && !entries.is_empty() {
+        let num_entries = entries.len();
+        for entry in entries {
+            replay_entry(context, entry)?;
+        }
+        let tantivy_context = &context.tantivyContext;
+        let mut writer = tantivy_context.writer.lock(&index_path)?;
+        commit_writer_with_payload(
+            &mut writer,
+            generation.map(|g| g.to_string()),
+            &tantivy_context.index_reader,
+            &index_path,
+        )?;
+        info!(
+            "Replayed {} uncommitted operations of {:?} text search index",
+            num_entries, index_path
+        );
+    }
+    let file = match OpenOptions::new().create(true).append(true).open(&wal_path) {
+        Ok(f) => f,
+        Err(e) => {
+            return Err(Error::other(format!(
+                "Unable to open the op log of {:?} text search index -> {}",
+                index_path, e
+            )));
+        }
+    };
+    let mut writer = context.tantivyContext.writer.lock(&index_path)?;
+    writer.wal = Some(Wal {
+        file,
+        // NOTE: clear starts the next generation.
+        generation: generation.max(committed_generation).unwrap_or(0),
+        sync_every,
+        unsynced: 0,
+    });
+    clear(&mut writer, &index_path)
+}
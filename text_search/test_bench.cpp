@@ -11,7 +11,7 @@ class MyFixture1 : public benchmark::Fixture {
 public:
   void SetUp(const ::benchmark::State &state) {
     if (!global_init_done) {
-      mgcxx::text_search::init("todo");
+      mgcxx::text_search::init("", nullptr);
       global_init_done = true;
     }
     index_path = create_temporary_directory("text_search_index_",
@@ -35,7 +35,7 @@ class MyFixture2 : public benchmark::Fixture {
 public:
   void SetUp(const ::benchmark::State &state) {
     if (!global_init_done) {
-      mgcxx::text_search::init("todo");
+      mgcxx::text_search::init("", nullptr);
       global_init_done = true;
     }
     index_path = create_temporary_directory("text_search_index_",
@@ -1,4 +1,11 @@
 #include "gtest/gtest.h"
+#include <chrono>
+#include <cmath>
+#include <filesystem>
+#include <fstream>
+#include <mutex>
+#include <set>
+#include <stdexcept>
 #include <thread>
 
 #include "test_util.hpp"
@@ -94,7 +101,7 @@ TEST(text_search_test_case, mappings) {
     mappings["properties"]["prop3"] = {
         {"type", "json"}, {"stored", true}, {"text", true}, {"fast", true}};
     mappings["properties"]["prop4"] = {
-        {"type", "bool"}, {"stored", true}, {"text", true}, {"fast", true}};
+        {"type", "bool"}, {"stored", true}, {"indexed", true}, {"fast", true}};
     auto context = mgcxx::text_search::create_index(
         index_name,
         mgcxx::text_search::IndexConfig{.mappings = mappings.dump()});
@@ -153,6 +160,1599 @@ TEST(text_search_test_case, limit_test) {
     auto regex_result = mgcxx::text_search::regex_search(context, regex_search_input);
     ASSERT_EQ(regex_result.docs.size(), 2);
 
+    mgcxx::text_search::SearchInput regex_no_field_input = {
+        .search_fields = {},
+        .search_query = ".*",
+        .return_fields = {"data"},
+        .aggregation_query = "",
+        .limit = 2};
+    EXPECT_THROW(
+        mgcxx::text_search::regex_search(context, regex_no_field_input),
+        ::rust::Error);
+
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, count_test) {
+  try {
+    auto index_name = "tantivy_index_count_test";
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+
+    for (const auto &doc : dummy_data1(10, 2)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::commit(context);
+
+    mgcxx::text_search::SearchInput count_input = {
+        .search_fields = {"metadata"}, .search_query = "data.key1:AWESOME"};
+    ASSERT_EQ(mgcxx::text_search::count(context, count_input), 10);
+
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"metadata"},
+        .search_query = "data.key1:AWESOME",
+        .return_fields = {"data"},
+        .limit = 3,
+        .count_total = true};
+    auto result = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(result.docs.size(), 3);
+    ASSERT_EQ(result.total_count, 10);
+
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, fast_fields_test) {
+  try {
+    auto index_name = "tantivy_index_fast_fields_test";
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings2().dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+
+    for (const auto &doc : dummy_data2(5, 2)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::commit(context);
+
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key1:AWESOME",
+        .fast_fields = {"gid", "data.key0"}};
+    auto result = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(result.docs.size(), 5);
+    std::set<uint64_t> gids;
+    for (const auto &doc : result.docs) {
+      // NOTE: Nothing is fetched from the docstore if return_fields is empty.
+      ASSERT_EQ(nlohmann::json::parse(doc.data), nlohmann::json::object());
+      auto fast_data = nlohmann::json::parse(doc.fast_data);
+      ASSERT_EQ(fast_data["data.key0"], "value0 is AWESOME");
+      gids.insert(fast_data["gid"].get<uint64_t>());
+    }
+    ASSERT_EQ(gids, (std::set<uint64_t>{0, 1, 2, 3, 4}));
+    mgcxx::text_search::drop_index(std::move(context));
+
+    // NOTE: A field which exists but is not fast is an error, not an empty value.
+    nlohmann::json mappings = {};
+    mappings["properties"] = {};
+    mappings["properties"]["gid"] = {
+        {"type", "u64"}, {"stored", true}, {"indexed", true}};
+    mappings["properties"]["data"] = {
+        {"type", "json"}, {"fast", true}, {"stored", true}, {"text", true}};
+    auto not_fast_context = mgcxx::text_search::create_index(
+        "tantivy_index_fast_fields_test_not_fast",
+        mgcxx::text_search::IndexConfig{.mappings = mappings.dump()});
+    for (const auto &doc : dummy_data2(1, 1)) {
+      mgcxx::text_search::add_document(not_fast_context, doc, false);
+    }
+    mgcxx::text_search::SearchInput not_fast_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:AWESOME",
+        .fast_fields = {"gid"}};
+    EXPECT_THROW(mgcxx::text_search::search(not_fast_context, not_fast_input),
+                 ::rust::Error);
+    mgcxx::text_search::drop_index(std::move(not_fast_context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, query_parser_options_test) {
+  try {
+    auto index_name = "tantivy_index_query_parser_options_test";
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+
+    for (const auto &doc : dummy_data1(5, 2)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::commit(context);
+
+    mgcxx::text_search::SearchInput or_input = {
+        .search_fields = {"data"}, .search_query = "data.key0:value0 data.key0:MISSING"};
+    ASSERT_EQ(mgcxx::text_search::search(context, or_input).docs.size(), 5);
+    mgcxx::text_search::SearchInput and_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:value0 data.key0:MISSING",
+        .conjunction_by_default = true};
+    ASSERT_EQ(mgcxx::text_search::search(context, and_input).docs.size(), 0);
+
+    mgcxx::text_search::SearchInput boosted_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:value0",
+        .field_boosts = {{.field = "data", .boost = 2.0f}}};
+    auto boosted_result = mgcxx::text_search::search(context, boosted_input);
+    mgcxx::text_search::SearchInput plain_input = {
+        .search_fields = {"data"}, .search_query = "data.key0:value0"};
+    auto result = mgcxx::text_search::search(context, plain_input);
+    ASSERT_GT(boosted_result.docs[0].score, result.docs[0].score);
+
+    mgcxx::text_search::SearchInput malformed_input = {
+        .search_fields = {"data"}, .search_query = "data.key0:value0 AND ("};
+    EXPECT_THROW(mgcxx::text_search::search(context, malformed_input),
+                 ::rust::Error);
+    malformed_input.lenient = true;
+    auto lenient_result =
+        mgcxx::text_search::search(context, malformed_input);
+    ASSERT_EQ(lenient_result.docs.size(), 5);
+    ASSERT_FALSE(lenient_result.warnings.empty());
+
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, scoring_test) {
+  try {
+    auto index_name = "tantivy_index_scoring_test";
+    nlohmann::json settings = {};
+    settings["bm25"] = {{"k1", 1.5}, {"b", 0.5}};
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings2().dump(),
+                                        .settings = settings.dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+
+    for (const auto &doc : dummy_data2(5, 2)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::commit(context);
+
+    nlohmann::json score_tweak = {};
+    score_tweak["field"] = "gid";
+    score_tweak["function"] = "multiply";
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:value0",
+        .fast_fields = {"gid"},
+        .score_tweak = score_tweak.dump()};
+    auto result = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(result.docs.size(), 5);
+    // NOTE: All documents have the same text score -> ordered by gid.
+    for (uint64_t i = 0; i < 5; ++i) {
+      auto fast_data = nlohmann::json::parse(result.docs[i].fast_data);
+      ASSERT_EQ(fast_data["gid"].get<uint64_t>(), 4 - i);
+    }
+    ASSERT_EQ(result.docs[4].score, 0.0f);
+
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, bm25_test) {
+  nlohmann::json mappings = {};
+  mappings["properties"] = {};
+  mappings["properties"]["id"] = {
+      {"type", "u64"}, {"fast", true}, {"stored", true}, {"indexed", true}};
+  mappings["properties"]["title"] = {
+      {"type", "text"}, {"stored", true}, {"text", true}};
+  mappings["properties"]["body"] = {
+      {"type", "text"}, {"stored", true}, {"text", true}};
+  // NOTE: Document 1 has a short body mentioning apple once, document 2 a long body mentioning
+  // it many times.
+  std::vector<nlohmann::json> docs = {
+      {{"id", 1}, {"title", "x"}, {"body", "apple"}},
+      {{"id", 2},
+       {"title", "y"},
+       {"body", "apple apple apple apple pear pear pear pear pear pear pear pear "
+                "pear pear pear pear pear pear pear"}}};
+  for (uint64_t id = 3; id < 20; ++id) {
+    docs.push_back({{"id", id}, {"title", "z"}, {"body", "pear pear plum"}});
+  }
+  auto top_id = [&](const std::string &settings,
+                    mgcxx::text_search::SearchInput search_input) {
+    auto index_config = mgcxx::text_search::IndexConfig{
+        .mappings = mappings.dump(), .settings = settings};
+    auto context = mgcxx::text_search::create_index("tantivy_index_bm25_test",
+                                                    index_config);
+    for (const auto &doc : docs) {
+      mgcxx::text_search::add_document(
+          context, mgcxx::text_search::DocumentInput{.data = doc.dump()},
+          true);
+    }
+    mgcxx::text_search::commit(context);
+    search_input.fast_fields = {"id"};
+    auto result = mgcxx::text_search::search(context, search_input);
+    mgcxx::text_search::drop_index(std::move(context));
+    return nlohmann::json::parse(result.docs.at(0).fast_data)["id"]
+        .get<uint64_t>();
+  };
+  try {
+    nlohmann::json settings = {};
+    // NOTE: b = 0 ignores the body length, a high k1 rewards the term frequency.
+    settings["bm25"] = {{"k1", 5.0}, {"b", 0.0}};
+    mgcxx::text_search::SearchInput apple_input = {
+        .search_fields = {"body"}, .search_query = "body:apple"};
+    ASSERT_EQ(top_id("", apple_input), 1);
+    ASSERT_EQ(top_id(settings.dump(), apple_input), 2);
+
+    // NOTE: The boosts still apply with the custom parameters.
+    mgcxx::text_search::SearchInput title_input = {
+        .search_fields = {"body"}, .search_query = "body:apple title:x"};
+    ASSERT_EQ(top_id(settings.dump(), title_input), 2);
+    mgcxx::text_search::SearchInput query_boost_input = {
+        .search_fields = {"body"}, .search_query = "body:apple title:x^20"};
+    ASSERT_EQ(top_id(settings.dump(), query_boost_input), 1);
+    mgcxx::text_search::SearchInput field_boost_input = {
+        .search_fields = {"body"},
+        .search_query = "body:apple title:x",
+        .field_boosts = {{.field = "title", .boost = 20.0f}}};
+    ASSERT_EQ(top_id(settings.dump(), field_boost_input), 1);
+
+    auto index_config = mgcxx::text_search::IndexConfig{
+        .mappings = mappings.dump(), .settings = settings.dump()};
+    auto context = mgcxx::text_search::create_index("tantivy_index_bm25_test",
+                                                    index_config);
+    for (const auto &doc : docs) {
+      mgcxx::text_search::add_document(
+          context, mgcxx::text_search::DocumentInput{.data = doc.dump()},
+          true);
+    }
+    mgcxx::text_search::commit(context);
+    auto top_score = [&](mgcxx::text_search::SearchInput search_input) {
+      return mgcxx::text_search::search(context, search_input)
+          .docs.at(0)
+          .score;
+    };
+    // NOTE: b = 0 -> the apple score of document 2 is
+    // idf * (k1 + 1) * tf / (tf + k1) = ln(1 + 17.5 / 2.5) * 6 * 4 / 9.
+    auto apple_score = top_score(apple_input);
+    ASSERT_NEAR(apple_score, std::log(8.0f) * 6.0f * 4.0f / 9.0f, 1e-4);
+    ASSERT_NEAR(top_score({.search_fields = {"body"},
+                           .search_query = "body:apple^3"}),
+                3.0f * apple_score, 1e-4);
+    ASSERT_NEAR(top_score({.search_fields = {"body"},
+                           .search_query = "body:apple",
+                           .field_boosts = {{.field = "body", .boost = 3.0f}}}),
+                3.0f * apple_score, 1e-4);
+    ASSERT_NEAR(top_score({.search_fields = {"body"},
+                           .search_query = "body:apple^2",
+                           .field_boosts = {{.field = "body", .boost = 3.0f}}}),
+                6.0f * apple_score, 1e-4);
+    // NOTE: Phrases can't be scored with the custom parameters, explicit and
+    // implicit ones (a term split into multiple tokens) are rejected.
+    EXPECT_THROW(mgcxx::text_search::search(
+                     context, {.search_fields = {"body"},
+                               .search_query = "body:\"apple pear\""}),
+                 ::rust::Error);
+    EXPECT_THROW(mgcxx::text_search::search(
+                     context, {.search_fields = {"body"},
+                               .search_query = "body:apple-pear",
+                               .lenient = true}),
+                 ::rust::Error);
+    mgcxx::text_search::drop_index(std::move(context));
+
+    // NOTE: Without the custom parameters, phrases are scored by tantivy.
+    auto default_context = mgcxx::text_search::create_index(
+        "tantivy_index_bm25_test",
+        mgcxx::text_search::IndexConfig{.mappings = mappings.dump()});
+    for (const auto &doc : docs) {
+      mgcxx::text_search::add_document(
+          default_context,
+          mgcxx::text_search::DocumentInput{.data = doc.dump()}, true);
+    }
+    mgcxx::text_search::commit(default_context);
+    auto phrase_result = mgcxx::text_search::search(
+        default_context, {.search_fields = {"body"},
+                          .search_query = "body:\"apple pear\""});
+    ASSERT_EQ(phrase_result.docs.size(), 1);
+    ASSERT_GT(phrase_result.docs[0].score, 0.0f);
+    mgcxx::text_search::drop_index(std::move(default_context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, min_score_and_dedup_test) {
+  try {
+    auto index_name = "tantivy_index_min_score_and_dedup_test";
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings2().dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+
+    // NOTE: Each gid is indexed twice (e.g. multiple documents per node).
+    for (int i = 0; i < 2; ++i) {
+      for (const auto &doc : dummy_data2(5, 2)) {
+        mgcxx::text_search::add_document(context, doc, true);
+      }
+    }
+    mgcxx::text_search::commit(context);
+
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:value0",
+        .count_total = true,
+        .fast_fields = {"gid"}};
+    auto result = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(result.docs.size(), 10);
+    ASSERT_EQ(result.total_count, 10);
+
+    search_input.dedup_field = "gid";
+    auto dedup_result = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(dedup_result.docs.size(), 5);
+    ASSERT_EQ(dedup_result.total_count, 5);
+    std::set<uint64_t> gids;
+    for (const auto &doc : dedup_result.docs) {
+      gids.insert(
+          nlohmann::json::parse(doc.fast_data)["gid"].get<uint64_t>());
+    }
+    ASSERT_EQ(gids.size(), 5);
+
+    // NOTE: Only the keys which can still make it into the top limit hits are
+    // kept, all of them are counted.
+    search_input.limit = 2;
+    auto limited_result = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(limited_result.docs.size(), 2);
+    ASSERT_EQ(limited_result.total_count, 5);
+    ASSERT_NE(
+        nlohmann::json::parse(limited_result.docs[0].fast_data)["gid"],
+        nlohmann::json::parse(limited_result.docs[1].fast_data)["gid"]);
+    search_input.limit = 0;
+
+    search_input.min_score = result.docs[0].score + 1.0f;
+    auto threshold_result = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(threshold_result.docs.size(), 0);
+    ASSERT_EQ(threshold_result.total_count, 0);
+
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, log_callback_test) {
+  // NOTE: Resets the callback even if an assertion fails, the callback
+  // captures state of this test only.
+  struct ResetLogCallback {
+    ~ResetLogCallback() { mgcxx::text_search::init("", nullptr); }
+  } reset_log_callback;
+  try {
+    auto messages = std::make_shared<std::vector<std::string>>();
+    auto messages_lock = std::make_shared<std::mutex>();
+    mgcxx::text_search::init(
+        "debug", mgcxx::text_search::make_log_callback(
+                     [messages, messages_lock](
+                         mgcxx::text_search::LogLevel level,
+                         const std::string &target, const std::string &message) {
+                       std::lock_guard<std::mutex> guard(*messages_lock);
+                       messages->push_back(fmt::format("{}: {}", target, message));
+                     }));
+
+    auto index_name = "tantivy_index_log_callback_test";
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+    for (const auto &doc : dummy_data1(1, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    mgcxx::text_search::drop_index(std::move(context));
+    {
+      std::lock_guard<std::mutex> guard(*messages_lock);
+      ASSERT_FALSE(messages->empty());
+    }
+
+    // NOTE: Reinitialization is allowed, it just replaces the level and the
+    // callback (also from the callback itself).
+    EXPECT_THROW(mgcxx::text_search::init("todo", nullptr), ::rust::Error);
+    mgcxx::text_search::init(
+        "debug", mgcxx::text_search::make_log_callback(
+                     [](mgcxx::text_search::LogLevel level,
+                        const std::string &target, const std::string &message) {
+                       mgcxx::text_search::init("debug", nullptr);
+                       throw std::runtime_error("swallowed");
+                     }));
+    auto reinit_context =
+        mgcxx::text_search::create_index(index_name, index_config);
+    mgcxx::text_search::drop_index(std::move(reinit_context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, metrics_test) {
+  try {
+    // NOTE: Metrics are process wide -> compare against the state before the
+    // test.
+    auto metrics_before =
+        nlohmann::json::parse(mgcxx::text_search::get_metrics());
+
+    auto index_name = "tantivy_index_metrics_test";
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+    for (const auto &doc : dummy_data1(5, 1)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::commit(context);
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"metadata"}, .search_query = "data.key0:AWESOME"};
+    mgcxx::text_search::search(context, search_input);
+
+    auto metrics = nlohmann::json::parse(mgcxx::text_search::get_metrics());
+    ASSERT_EQ(metrics["documents_added_total"].get<uint64_t>() -
+                  metrics_before["documents_added_total"].get<uint64_t>(),
+              5);
+    ASSERT_EQ(metrics["commits_total"].get<uint64_t>() -
+                  metrics_before["commits_total"].get<uint64_t>(),
+              1);
+    ASSERT_EQ(metrics["searches_total"].get<uint64_t>() -
+                  metrics_before["searches_total"].get<uint64_t>(),
+              1);
+    ASSERT_EQ(metrics["search_latency"]["count"].get<uint64_t>() -
+                  metrics_before["search_latency"]["count"].get<uint64_t>(),
+              1);
+    ASSERT_EQ(metrics["commit_duration"]["buckets"].back()["count"],
+              metrics["commit_duration"]["count"]);
+
+    auto index_metrics =
+        nlohmann::json::parse(mgcxx::text_search::get_index_metrics(context));
+    ASSERT_EQ(index_metrics["num_docs"], 5);
+    ASSERT_GT(index_metrics["size_bytes"].get<uint64_t>(), 0);
+
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, index_manager_test) {
+  try {
+    auto manager = mgcxx::text_search::create_index_manager(
+        mgcxx::text_search::IndexManagerConfig{
+            .writer_memory_budget = 40'000'000, .search_threads = 2});
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    manager->create_index("label1", "tantivy_index_manager_test_label1",
+                          index_config);
+    // NOTE: Pending operations are kept while the writer memory is shared
+    // with the next index.
+    mgcxx::text_search::add_document(manager->get_index("label1"),
+                                     dummy_data1(1, 1)[0], true);
+    manager->create_index("label2", "tantivy_index_manager_test_label2",
+                          index_config);
+    mgcxx::text_search::commit(manager->get_index("label1"));
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(manager->get_index("label1")),
+              1);
+    // NOTE: One writer per directory, also across index managers.
+    EXPECT_THROW(manager->create_index("label3",
+                                       "tantivy_index_manager_test_label1",
+                                       index_config),
+                 ::rust::Error);
+    auto other_manager = mgcxx::text_search::create_index_manager(
+        mgcxx::text_search::IndexManagerConfig{});
+    EXPECT_THROW(other_manager->create_index(
+                     "label1", "tantivy_index_manager_test_label1",
+                     index_config),
+                 ::rust::Error);
+    // NOTE: The writer memory budget can't be shared by 3 writers (15MB each
+    // is the minimum).
+    EXPECT_THROW(manager->create_index("label3",
+                                       "tantivy_index_manager_test_label3",
+                                       index_config),
+                 ::rust::Error);
+    // NOTE: Managed contexts can't be replaced outside of the manager.
+    auto reindex_handle = mgcxx::text_search::reindex(
+        manager->get_index("label2"), "tantivy_index_manager_test_reindex",
+        index_config, "");
+    EXPECT_THROW(mgcxx::text_search::switch_to_reindex_target(
+                     std::move(reindex_handle), manager->get_index("label2")),
+                 ::rust::Error);
+    std::filesystem::remove_all("tantivy_index_manager_test_reindex");
+    auto indexes = manager->list_indexes();
+    ASSERT_EQ(indexes.size(), 2);
+    ASSERT_EQ(indexes[0], "label1");
+    ASSERT_EQ(indexes[1], "label2");
+
+    auto &context = manager->get_index("label1");
+    for (const auto &doc : dummy_data1(5, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 6);
+    EXPECT_THROW(manager->get_index("label3"), ::rust::Error);
+
+    // NOTE: Closing keeps the data on disk and releases the directory.
+    manager->close_index("label1", false);
+    other_manager->create_index("label1", "tantivy_index_manager_test_label1",
+                                index_config);
+    other_manager->close_index("label1", false);
+    manager->create_index("label1", "tantivy_index_manager_test_label1",
+                          index_config);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(manager->get_index("label1")),
+              6);
+
+    manager->drop_index("label1");
+    manager->drop_index("label2");
+    ASSERT_EQ(manager->list_indexes().size(), 0);
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, search_many_test) {
+  try {
+    auto manager = mgcxx::text_search::create_index_manager(
+        mgcxx::text_search::IndexManagerConfig{});
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    manager->create_index("label1", "tantivy_index_search_many_test_label1",
+                          index_config);
+    manager->create_index("label2", "tantivy_index_search_many_test_label2",
+                          index_config);
+    for (const auto &doc : dummy_data1(3, 1)) {
+      mgcxx::text_search::add_document(manager->get_index("label1"), doc,
+                                       false);
+    }
+    for (const auto &doc : dummy_data1(4, 1)) {
+      mgcxx::text_search::add_document(manager->get_index("label2"), doc,
+                                       false);
+    }
+
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"metadata"},
+        .search_query = "data.key0:AWESOME",
+        .return_fields = {"data"},
+        .limit = 5,
+        .count_total = true};
+    auto result = manager->search_many({}, search_input);
+    ASSERT_EQ(result.docs.size(), 5);
+    ASSERT_EQ(result.total_count, 7);
+    for (size_t i = 1; i < result.docs.size(); ++i) {
+      ASSERT_GE(result.docs[i - 1].score, result.docs[i].score);
+    }
+
+    result = manager->search_many({"label1"}, search_input);
+    ASSERT_EQ(result.docs.size(), 3);
+    for (const auto &doc : result.docs) {
+      ASSERT_EQ(doc.index_name, "label1");
+    }
+    EXPECT_THROW(manager->search_many({"label3"}, search_input),
+                 ::rust::Error);
+    // NOTE: Duplicated names are searched once.
+    result = manager->search_many({"label1", "label1"}, search_input);
+    ASSERT_EQ(result.docs.size(), 3);
+    ASSERT_EQ(result.total_count, 3);
+    auto cancelled_token = mgcxx::text_search::create_cancellation_token();
+    cancelled_token->cancel();
+    EXPECT_THROW(
+        manager->search_many_cancellable({}, search_input, *cancelled_token),
+        ::rust::Error);
+
+    manager->drop_index("label1");
+    manager->drop_index("label2");
+
+    std::vector<mgcxx::text_search::Context> contexts;
+    contexts.push_back(mgcxx::text_search::create_index(
+        "tantivy_index_search_many_test_context1", index_config));
+    contexts.push_back(mgcxx::text_search::create_index(
+        "tantivy_index_search_many_test_context2", index_config));
+    for (auto &context : contexts) {
+      for (const auto &doc : dummy_data1(2, 1)) {
+        mgcxx::text_search::add_document(context, doc, false);
+      }
+    }
+    auto contexts_slice = rust::Slice<const mgcxx::text_search::Context>(
+        contexts.data(), contexts.size());
+    result = mgcxx::text_search::search_many(contexts_slice, search_input);
+    ASSERT_EQ(result.docs.size(), 4);
+    ASSERT_EQ(result.total_count, 4);
+
+    EXPECT_THROW(mgcxx::text_search::search_many_cancellable(
+                     contexts_slice, search_input, *cancelled_token),
+                 ::rust::Error);
+    search_input.partial_results = true;
+    result = mgcxx::text_search::search_many_cancellable(
+        contexts_slice, search_input, *cancelled_token);
+    ASSERT_EQ(result.docs.size(), 0);
+    ASSERT_EQ(result.warnings.size(), 2);
+    for (auto &context : contexts) {
+      mgcxx::text_search::drop_index(std::move(context));
+    }
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, close_index_test) {
+  try {
+    auto index_name = "tantivy_index_close_index_test";
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+    for (const auto &doc : dummy_data1(2, 1)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::close_index(std::move(context), true);
+
+    // NOTE: The data is still there and the directory is not locked anymore.
+    context = mgcxx::text_search::create_index(index_name, index_config);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 2);
+    for (const auto &doc : dummy_data1(3, 1)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::close_index(std::move(context), false);
+
+    context = mgcxx::text_search::create_index(index_name, index_config);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 2);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, alter_index_test) {
+  try {
+    auto index_name = "tantivy_index_alter_index_test";
+    auto mappings = dummy_mappings1();
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = mappings.dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+    for (const auto &doc : dummy_data1(2, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+
+    auto new_mappings = mappings;
+    new_mappings["properties"]["all"] = {
+        {"type", "text"}, {"stored", true}, {"text", true}};
+    mgcxx::text_search::alter_index(context, new_mappings.dump());
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 2);
+    mgcxx::text_search::add_document(
+        context,
+        mgcxx::text_search::DocumentInput{
+            .data = nlohmann::json{{"metadata", {{"gid", 2}}},
+                                   {"data", {{"key0", "value0"}}},
+                                   {"all", "value0 is NEW"}}
+                        .dump()},
+        false);
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"all"}, .search_query = "NEW"};
+    ASSERT_EQ(mgcxx::text_search::count(context, search_input), 1);
+
+    // NOTE: Removing or changing fields requires reindexing.
+    auto incompatible_mappings = new_mappings;
+    incompatible_mappings["properties"].erase("data");
+    EXPECT_THROW(
+        mgcxx::text_search::alter_index(context, incompatible_mappings.dump()),
+        ::rust::Error);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 3);
+
+    mgcxx::text_search::close_index(std::move(context), true);
+    EXPECT_THROW(mgcxx::text_search::create_index(index_name, index_config),
+                 ::rust::Error);
+    context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = new_mappings.dump()});
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 3);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, alter_index_reopen_failure_test) {
+  try {
+    auto index_name = "tantivy_index_alter_index_reopen_failure_test";
+    auto mappings = dummy_mappings1();
+    auto index_config = mgcxx::text_search::IndexConfig{
+        .mappings = mappings.dump(),
+        .settings = R"({"wal": {"sync_every": 1}})"};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+    for (const auto &doc : dummy_data1(2, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+
+    // NOTE: The open log file is still usable, but opening the index again
+    // fails because the log can't be read.
+    auto wal_path = std::filesystem::path(index_name) / "mgcxx_wal.jsonl";
+    std::filesystem::remove(wal_path);
+    std::filesystem::create_directory(wal_path);
+    auto new_mappings = mappings;
+    new_mappings["properties"]["all"] = {
+        {"type", "text"}, {"stored", true}, {"text", true}};
+    EXPECT_THROW(mgcxx::text_search::alter_index(context, new_mappings.dump()),
+                 ::rust::Error);
+    for (const auto &doc : dummy_data1(1, 1)) {
+      EXPECT_THROW(mgcxx::text_search::add_document(context, doc, false),
+                   ::rust::Error);
+    }
+    EXPECT_THROW(mgcxx::text_search::commit(context), ::rust::Error);
+
+    std::filesystem::remove(wal_path);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, alter_index_meta_failure_test) {
+  try {
+    auto index_name = "tantivy_index_alter_index_meta_failure_test";
+    auto mappings = dummy_mappings1();
+    auto new_mappings = mappings;
+    new_mappings["properties"]["all"] = {
+        {"type", "text"}, {"stored", true}, {"text", true}};
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = mappings.dump()};
+    auto new_index_config =
+        mgcxx::text_search::IndexConfig{.mappings = new_mappings.dump()};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+    for (const auto &doc : dummy_data1(2, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+
+    // NOTE: The altered metadata can't be written -> the index stays as it
+    // was and the leftover is removed once the index is opened again.
+    auto altered_meta_path =
+        std::filesystem::path(index_name) / "mgcxx_alter_meta.json";
+    std::filesystem::create_directory(altered_meta_path);
+    EXPECT_THROW(mgcxx::text_search::alter_index(context, new_mappings.dump()),
+                 ::rust::Error);
+    ASSERT_FALSE(std::filesystem::exists(altered_meta_path));
+    for (const auto &doc : dummy_data1(1, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 3);
+    mgcxx::text_search::close_index(std::move(context), true);
+    EXPECT_THROW(mgcxx::text_search::create_index(index_name, new_index_config),
+                 ::rust::Error);
+
+    // NOTE: Same as a crash right before the metadata is replaced.
+    context = mgcxx::text_search::create_index(index_name, index_config);
+    mgcxx::text_search::close_index(std::move(context), true);
+    { std::ofstream(altered_meta_path) << "{"; }
+    context = mgcxx::text_search::create_index(index_name, index_config);
+    ASSERT_FALSE(std::filesystem::exists(altered_meta_path));
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 3);
+
+    mgcxx::text_search::alter_index(context, new_mappings.dump());
+    mgcxx::text_search::close_index(std::move(context), true);
+    context = mgcxx::text_search::create_index(index_name, new_index_config);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 3);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, reindex_test) {
+  try {
+    auto index_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    auto context = mgcxx::text_search::create_index(
+        "tantivy_index_reindex_test_source", index_config);
+    for (const auto &doc : dummy_data1(10, 1)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::commit(context);
+
+    nlohmann::json new_mappings = {};
+    new_mappings["properties"] = {};
+    new_mappings["properties"]["metadata"] = {
+        {"type", "json"}, {"fast", true}, {"stored", true}, {"text", true}};
+    new_mappings["properties"]["props"] = {
+        {"type", "json"}, {"stored", true}, {"text", true}};
+    auto handle = mgcxx::text_search::reindex(
+        context, "tantivy_index_reindex_test_target",
+        mgcxx::text_search::IndexConfig{.mappings = new_mappings.dump()},
+        R"({"rename": {"data": "props"}})");
+    while (!handle->progress().done) {
+      std::this_thread::sleep_for(std::chrono::milliseconds(1));
+    }
+    auto progress = handle->progress();
+    ASSERT_EQ(progress.processed, 10);
+    ASSERT_EQ(progress.total, 10);
+    ASSERT_EQ(progress.error, "");
+
+    auto old_context = mgcxx::text_search::switch_to_reindex_target(
+        std::move(handle), context);
+    mgcxx::text_search::drop_index(std::move(old_context));
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"metadata"},
+        .search_query = "props.key0:AWESOME",
+        .return_fields = {"props"}};
+    auto result = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(result.docs.size(), 10);
+
+    // NOTE: Nothing is renamed on disk -> the index is opened from the target.
+    mgcxx::text_search::close_index(std::move(context), true);
+    context = mgcxx::text_search::create_index(
+        "tantivy_index_reindex_test_target",
+        mgcxx::text_search::IndexConfig{.mappings = new_mappings.dump()});
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 10);
+    mgcxx::text_search::drop_index(std::move(context));
+
+    // NOTE: Values of fields which aren't stored can't be copied.
+    auto unstored_mappings = dummy_mappings1();
+    unstored_mappings["properties"]["data"]["stored"] = false;
+    context = mgcxx::text_search::create_index(
+        "tantivy_index_reindex_test_unstored",
+        mgcxx::text_search::IndexConfig{.mappings = unstored_mappings.dump()});
+    auto target_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()};
+    EXPECT_THROW(mgcxx::text_search::reindex(
+                     context, "tantivy_index_reindex_test_unstored_target",
+                     target_config, ""),
+                 ::rust::Error);
+    handle = mgcxx::text_search::reindex(
+        context, "tantivy_index_reindex_test_unstored_target", target_config,
+        R"({"remove": ["data"]})");
+    old_context = mgcxx::text_search::switch_to_reindex_target(
+        std::move(handle), context);
+    mgcxx::text_search::drop_index(std::move(old_context));
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, validate_mappings_test) {
+  nlohmann::json mappings = {};
+  mappings["properties"] = {};
+  mappings["properties"]["prop1"] = {
+      {"type", "u64"}, {"fast", true}, {"indexed", true}};
+  mappings["properties"]["prop2"] = {
+      {"type", "text"}, {"stored", true}, {"text", true}};
+  EXPECT_NO_THROW(mgcxx::text_search::validate_mappings(mappings.dump()));
+
+  mappings["properties"]["prop3"] = {{"type", "json"}, {"stord", true}};
+  mappings["properties"]["prop4"] = {{"type", "u64"}, {"text", true}};
+  mappings["properties"]["prop5"] = {{"type", "float"}};
+  try {
+    mgcxx::text_search::validate_mappings(mappings.dump());
+    FAIL() << "Invalid mappings passed the validation";
+  } catch (const ::rust::Error &error) {
+    // NOTE: All problems are reported at once.
+    std::string message = error.what();
+    EXPECT_NE(message.find("prop3"), std::string::npos);
+    EXPECT_NE(message.find("stord"), std::string::npos);
+    EXPECT_NE(message.find("prop4"), std::string::npos);
+    EXPECT_NE(message.find("prop5"), std::string::npos);
+  }
+  EXPECT_THROW(mgcxx::text_search::create_index(
+                   "tantivy_index_validate_mappings_test",
+                   mgcxx::text_search::IndexConfig{.mappings = mappings.dump()}),
+               ::rust::Error);
+}
+
+TEST(text_search_test_case, get_mappings_test) {
+  try {
+    auto index_name = "tantivy_index_get_mappings_test";
+    nlohmann::json mappings = {};
+    mappings["properties"] = {};
+    mappings["properties"]["gid"] = {
+        {"type", "u64"}, {"fast", true}, {"indexed", true}};
+    mappings["properties"]["name"] = {{"type", "text"},
+                                      {"stored", true},
+                                      {"text", true},
+                                      {"tokenizer", "raw"}};
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = mappings.dump()});
+
+    auto exported = mgcxx::text_search::get_mappings(context);
+    auto exported_json = nlohmann::json::parse(exported);
+    ASSERT_EQ(exported_json["properties"]["gid"]["type"], "u64");
+    ASSERT_EQ(exported_json["properties"]["gid"]["fast"], true);
+    ASSERT_EQ(exported_json["properties"]["gid"]["stored"], false);
+    ASSERT_EQ(exported_json["properties"]["name"]["tokenizer"], "raw");
+    EXPECT_NO_THROW(mgcxx::text_search::validate_mappings(exported));
+
+    // NOTE: The exported mappings describe exactly the same index.
+    mgcxx::text_search::close_index(std::move(context), true);
+    context = mgcxx::text_search::create_index(
+        index_name, mgcxx::text_search::IndexConfig{.mappings = exported});
+    mgcxx::text_search::drop_index(std::move(context));
+
+    // NOTE: A new index created from the exported mappings keeps the
+    // non-default tokenizers.
+    context = mgcxx::text_search::create_index(
+        index_name, mgcxx::text_search::IndexConfig{.mappings = exported});
+    ASSERT_EQ(nlohmann::json::parse(mgcxx::text_search::get_mappings(context)),
+              exported_json);
+    mgcxx::text_search::add_document(
+        context,
+        mgcxx::text_search::DocumentInput{
+            .data = nlohmann::json{{"gid", 1}, {"name", "Graph Database"}}
+                        .dump()},
+        false);
+    mgcxx::text_search::commit(context);
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"name"}, .search_query = "Graph"};
+    ASSERT_EQ(mgcxx::text_search::count(context, search_input), 0);
+    search_input.search_query = "\"Graph Database\"";
+    ASSERT_EQ(mgcxx::text_search::count(context, search_input), 1);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, get_document_test) {
+  try {
+    auto index_name = "tantivy_index_get_document_test";
+    auto index_config = mgcxx::text_search::IndexConfig{
+        .mappings = dummy_mappings1().dump(),
+        .settings = R"({"key_field": "metadata.gid"})"};
+    auto context = mgcxx::text_search::create_index(index_name, index_config);
+    for (const auto &doc : dummy_data1(3, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    mgcxx::text_search::commit(context);
+
+    auto doc = mgcxx::text_search::get_document(context, "1");
+    auto data = nlohmann::json::parse(doc.data);
+    ASSERT_EQ(data["metadata"]["gid"], 1);
+    ASSERT_EQ(data["data"]["key0"], "value0 is AWESOME");
+
+    auto docs = mgcxx::text_search::get_documents(context, {"2", "7", "0"});
+    ASSERT_EQ(docs.size(), 3);
+    ASSERT_EQ(nlohmann::json::parse(docs[0].data)["metadata"]["gid"], 2);
+    // NOTE: Missing documents are returned with empty data.
+    ASSERT_EQ(docs[1].data, "");
+    ASSERT_EQ(nlohmann::json::parse(docs[2].data)["metadata"]["gid"], 0);
+    // NOTE: Keys are values, not queries.
+    EXPECT_THROW(
+        mgcxx::text_search::get_document(context, "1 OR metadata.gid:2"),
+        ::rust::Error);
+    mgcxx::text_search::drop_index(std::move(context));
+
+    // NOTE: Tokenized text would match longer values.
+    nlohmann::json mappings = {};
+    mappings["properties"] = {};
+    mappings["properties"]["name"] = {{"type", "text"},
+                                      {"stored", true},
+                                      {"text", true},
+                                      {"tokenizer", "raw"}};
+    index_config = mgcxx::text_search::IndexConfig{
+        .mappings = mappings.dump(), .settings = R"({"key_field": "name"})"};
+    context = mgcxx::text_search::create_index(index_name, index_config);
+    mgcxx::text_search::add_document(
+        context,
+        mgcxx::text_search::DocumentInput{
+            .data = nlohmann::json{{"name", "Graph Database"}}.dump()},
+        false);
+    ASSERT_EQ(mgcxx::text_search::get_document(context, "Graph").data, "");
+    doc = mgcxx::text_search::get_document(context, "Graph Database");
+    ASSERT_EQ(nlohmann::json::parse(doc.data)["name"], "Graph Database");
+    mgcxx::text_search::drop_index(std::move(context));
+    mappings["properties"]["name"].erase("tokenizer");
+    index_config.mappings = mappings.dump();
+    EXPECT_THROW(mgcxx::text_search::create_index(index_name, index_config),
+                 ::rust::Error);
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, delete_all_documents_test) {
+  try {
+    auto index_name = "tantivy_index_delete_all_documents_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()});
+    for (const auto &doc : dummy_data1(5, 1)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    mgcxx::text_search::commit(context);
+
+    mgcxx::text_search::delete_all_documents(context, true);
+    mgcxx::text_search::rollback(context);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 5);
+
+    mgcxx::text_search::delete_all_documents(context, false);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 0);
+    // NOTE: The index is still usable.
+    for (const auto &doc : dummy_data1(2, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 2);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, opstamps_test) {
+  try {
+    auto index_name = "tantivy_index_opstamps_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()});
+    auto docs = dummy_data1(3, 1);
+    auto first = mgcxx::text_search::add_document(context, docs[0], true);
+    auto second = mgcxx::text_search::add_document(context, docs[1], true);
+    ASSERT_LT(first, second);
+    auto committed = mgcxx::text_search::commit(context);
+    ASSERT_LT(second, committed);
+
+    auto discarded = mgcxx::text_search::add_document(context, docs[2], true);
+    ASSERT_LT(committed, discarded);
+    ASSERT_EQ(mgcxx::text_search::rollback(context), committed);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 2);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, searcher_snapshot_test) {
+  try {
+    auto index_name = "tantivy_index_searcher_snapshot_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()});
+    for (const auto &doc : dummy_data1(2, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    auto searcher = mgcxx::text_search::acquire_searcher(context);
+    auto generation = searcher->generation();
+
+    for (const auto &doc : dummy_data1(3, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:value0",
+        .return_fields = {"data"}};
+    // NOTE: The pinned searcher doesn't see the later commits.
+    ASSERT_EQ(searcher->get_num_docs(), 2);
+    ASSERT_EQ(searcher->search(search_input).docs.size(), 2);
+    ASSERT_EQ(searcher->count(search_input), 2);
+    ASSERT_EQ(mgcxx::text_search::count(context, search_input), 5);
+    ASSERT_EQ(searcher->generation(), generation);
+    mgcxx::text_search::release_searcher(std::move(searcher));
+
+    auto latest_searcher = mgcxx::text_search::acquire_searcher(context);
+    ASSERT_NE(latest_searcher->generation(), generation);
+    ASSERT_EQ(latest_searcher->get_num_docs(), 5);
+    mgcxx::text_search::release_searcher(std::move(latest_searcher));
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, transaction_test) {
+  try {
+    auto index_name = "tantivy_index_transaction_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()});
+    for (const auto &doc : dummy_data1(3, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:value0",
+        .return_fields = {"data"}};
+    mgcxx::text_search::SearchInput delete_input = {
+        .search_fields = {"metadata"}, .search_query = "metadata.gid:1"};
+
+    auto transaction = mgcxx::text_search::begin_transaction(context);
+    transaction->delete_document(delete_input);
+    for (const auto &doc : dummy_data1(2, 1)) {
+      transaction->add_document(doc);
+    }
+    // NOTE: The transaction sees its own changes, others don't.
+    ASSERT_EQ(transaction->count(search_input), 4);
+    ASSERT_EQ(transaction->search(search_input).docs.size(), 4);
+    ASSERT_EQ(mgcxx::text_search::count(context, search_input), 3);
+
+    mgcxx::text_search::commit_transaction(std::move(transaction), context,
+                                           false);
+    ASSERT_EQ(mgcxx::text_search::count(context, search_input), 4);
+
+    transaction = mgcxx::text_search::begin_transaction(context);
+    transaction->add_document(dummy_data1(1, 1)[0]);
+    mgcxx::text_search::rollback_transaction(std::move(transaction));
+    ASSERT_EQ(mgcxx::text_search::count(context, search_input), 4);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, mvcc_test) {
+  try {
+    auto index_name = "tantivy_index_mvcc_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump(),
+                                        .settings = R"({"mvcc": true})"});
+    auto docs = dummy_data1(2, 1);
+    docs[0].timestamp = 10;
+    docs[1].timestamp = 20;
+    for (const auto &doc : docs) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    mgcxx::text_search::SearchInput delete_input = {
+        .search_fields = {"metadata"}, .search_query = "metadata.gid:0"};
+    mgcxx::text_search::delete_document_at(context, delete_input, 30, false);
+
+    auto count_as_of = [&](uint64_t as_of_timestamp) {
+      mgcxx::text_search::SearchInput count_input = {
+          .search_fields = {"data"},
+          .search_query = "data.key0:value0",
+          .as_of_timestamp = as_of_timestamp};
+      return mgcxx::text_search::count(context, count_input);
+    };
+    ASSERT_EQ(count_as_of(5), 0);
+    ASSERT_EQ(count_as_of(15), 1);
+    ASSERT_EQ(count_as_of(25), 2);
+    ASSERT_EQ(count_as_of(35), 1);
+    // NOTE: 0 means the latest state.
+    ASSERT_EQ(count_as_of(0), 1);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 1);
+    // NOTE: Removing the document would hide it from the older transactions.
+    EXPECT_THROW(
+        mgcxx::text_search::delete_document(context, delete_input, false),
+        ::rust::Error);
+
+    auto transaction = mgcxx::text_search::begin_transaction(context);
+    EXPECT_THROW(transaction->delete_document(delete_input), ::rust::Error);
+    delete_input.search_query = "metadata.gid:1";
+    transaction->delete_document_at(delete_input, 40);
+    mgcxx::text_search::commit_transaction(std::move(transaction), context,
+                                           false);
+    ASSERT_EQ(count_as_of(35), 1);
+    ASSERT_EQ(count_as_of(45), 0);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 0);
+
+    mgcxx::text_search::purge_deleted(context, 30, false);
+    ASSERT_EQ(count_as_of(25), 1);
+    mgcxx::text_search::drop_index(std::move(context));
+
+    // NOTE: Deleted versions are copies of the stored fields.
+    auto unstored_mappings = dummy_mappings1();
+    unstored_mappings["properties"]["data"]["stored"] = false;
+    EXPECT_THROW(mgcxx::text_search::create_index(
+                     index_name, mgcxx::text_search::IndexConfig{
+                                     .mappings = unstored_mappings.dump(),
+                                     .settings = R"({"mvcc": true})"}),
+                 ::rust::Error);
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, mvcc_pending_delete_test) {
+  try {
+    auto mvcc_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump(),
+                                        .settings = R"({"mvcc": true})"};
+    mgcxx::text_search::SearchInput delete_input = {
+        .search_fields = {"metadata"}, .search_query = "metadata.gid:0"};
+    auto v1 = dummy_data1(1, 1)[0];
+    v1.timestamp = 10;
+    auto v2_data = nlohmann::json::parse(v1.data);
+    v2_data["data"]["key0"] = "value2 is AWESOME";
+    auto v2 = mgcxx::text_search::DocumentInput{.data = v2_data.dump(),
+                                                .timestamp = 20};
+    auto count_as_of = [](mgcxx::text_search::Context &context,
+                          const std::string &query,
+                          uint64_t as_of_timestamp) {
+      mgcxx::text_search::SearchInput count_input = {
+          .search_fields = {"data"},
+          .search_query = query,
+          .as_of_timestamp = as_of_timestamp};
+      return mgcxx::text_search::count(context, count_input);
+    };
+
+    // NOTE: The second delete must not mark the already deleted version again.
+    auto context = mgcxx::text_search::create_index(
+        "tantivy_index_mvcc_pending_delete_test", mvcc_config);
+    mgcxx::text_search::add_document(context, v1, false);
+    mgcxx::text_search::delete_document_at(context, delete_input, 20, true);
+    mgcxx::text_search::delete_document_at(context, delete_input, 30, true);
+    mgcxx::text_search::commit(context);
+    ASSERT_EQ(count_as_of(context, "data.key0:AWESOME", 15), 1);
+    ASSERT_EQ(count_as_of(context, "data.key0:AWESOME", 25), 0);
+    ASSERT_EQ(count_as_of(context, "data.key0:AWESOME", 35), 0);
+    mgcxx::text_search::drop_index(std::move(context));
+
+    // NOTE: Update (delete + add) followed by a delete, all uncommitted.
+    context = mgcxx::text_search::create_index(
+        "tantivy_index_mvcc_pending_delete_test", mvcc_config);
+    mgcxx::text_search::add_document(context, v1, false);
+    mgcxx::text_search::delete_document_at(context, delete_input, 20, true);
+    mgcxx::text_search::add_document(context, v2, true);
+    mgcxx::text_search::delete_document_at(context, delete_input, 30, true);
+    mgcxx::text_search::commit(context);
+    ASSERT_EQ(count_as_of(context, "data.key0:value0", 15), 1);
+    ASSERT_EQ(count_as_of(context, "data.key0:value2", 15), 0);
+    ASSERT_EQ(count_as_of(context, "data.key0:value0", 25), 0);
+    ASSERT_EQ(count_as_of(context, "data.key0:value2", 25), 1);
+    ASSERT_EQ(count_as_of(context, "data.key0:AWESOME", 35), 0);
+
+    // NOTE: A pending delete_document_at hides the committed version only
+    // from the searches at or after its timestamp.
+    mgcxx::text_search::add_document(
+        context,
+        mgcxx::text_search::DocumentInput{.data = v1.data, .timestamp = 40},
+        false);
+    auto transaction = mgcxx::text_search::begin_transaction(context);
+    transaction->delete_document_at(delete_input, 50);
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"data"}, .search_query = "data.key0:value0"};
+    search_input.as_of_timestamp = 45;
+    ASSERT_EQ(transaction->search(search_input).docs.size(), 1);
+    ASSERT_EQ(transaction->count(search_input), 1);
+    search_input.as_of_timestamp = 55;
+    ASSERT_EQ(transaction->search(search_input).docs.size(), 0);
+    ASSERT_EQ(transaction->count(search_input), 0);
+    search_input.as_of_timestamp = 0;
+    ASSERT_EQ(transaction->count(search_input), 0);
+    mgcxx::text_search::rollback_transaction(std::move(transaction));
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, reindex_mvcc_test) {
+  try {
+    auto mvcc_config =
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump(),
+                                        .settings = R"({"mvcc": true})"};
+    auto context = mgcxx::text_search::create_index(
+        "tantivy_index_reindex_mvcc_test_source", mvcc_config);
+    auto docs = dummy_data1(3, 1);
+    docs[0].timestamp = 10;
+    docs[1].timestamp = 20;
+    docs[2].timestamp = 30;
+    for (const auto &doc : docs) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    mgcxx::text_search::SearchInput delete_input = {
+        .search_fields = {"metadata"}, .search_query = "metadata.gid:0"};
+    mgcxx::text_search::delete_document_at(context, delete_input, 40, false);
+
+    auto count_as_of = [&](uint64_t as_of_timestamp) {
+      mgcxx::text_search::SearchInput count_input = {
+          .search_fields = {"data"},
+          .search_query = "data.key0:value0",
+          .as_of_timestamp = as_of_timestamp};
+      return mgcxx::text_search::count(context, count_input);
+    };
+    // NOTE: Only the versions which are not deleted are copied, they keep
+    // their created_ts.
+    auto handle = mgcxx::text_search::reindex(
+        context, "tantivy_index_reindex_mvcc_test_target", mvcc_config, "");
+    auto source = mgcxx::text_search::switch_to_reindex_target(
+        std::move(handle), context);
+    ASSERT_EQ(count_as_of(0), 2);
+    ASSERT_EQ(count_as_of(25), 1);
+    ASSERT_EQ(count_as_of(15), 0);
+    auto mvcc_target = mgcxx::text_search::switch_to_reindex_target(
+        mgcxx::text_search::reindex(
+            context, "tantivy_index_reindex_mvcc_test_plain_target",
+            mgcxx::text_search::IndexConfig{.mappings =
+                                                dummy_mappings1().dump()},
+            ""),
+        context);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 2);
+
+    mgcxx::text_search::drop_index(std::move(source));
+    mgcxx::text_search::drop_index(std::move(mvcc_target));
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, wal_test) {
+  try {
+    auto index_name = "tantivy_index_wal_test";
+    auto config = mgcxx::text_search::IndexConfig{
+        .mappings = dummy_mappings1().dump(),
+        .settings = R"({"wal": {"sync_every": 1}})"};
+    auto context = mgcxx::text_search::create_index(index_name, config);
+    auto docs = dummy_data1(3, 1);
+    mgcxx::text_search::add_document(context, docs[0], false);
+    mgcxx::text_search::add_document(context, docs[1], true);
+    mgcxx::text_search::add_document(context, docs[2], true);
+
+    // NOTE: Simulates a crash by restoring the log after the pending changes
+    // are rolled back.
+    auto wal_path = std::filesystem::path(index_name) / "mgcxx_wal.jsonl";
+    auto crashed_wal_path = std::filesystem::path("tantivy_wal_test.jsonl");
+    std::filesystem::copy_file(wal_path, crashed_wal_path,
+                               std::filesystem::copy_options::overwrite_existing);
+    mgcxx::text_search::close_index(std::move(context), false);
+    std::filesystem::copy_file(crashed_wal_path, wal_path,
+                               std::filesystem::copy_options::overwrite_existing);
+    std::filesystem::remove(crashed_wal_path);
+
+    auto recovered_context = mgcxx::text_search::create_index(index_name, config);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(recovered_context), 3);
+    mgcxx::text_search::drop_index(std::move(recovered_context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, wal_recovery_after_commits_test) {
+  try {
+    auto index_name = "tantivy_index_wal_recovery_after_commits_test";
+    auto config = mgcxx::text_search::IndexConfig{
+        .mappings = dummy_mappings1().dump(),
+        .settings = R"({"wal": {"sync_every": 1}})"};
+    auto wal_path = std::filesystem::path(index_name) / "mgcxx_wal.jsonl";
+    auto crashed_wal_path =
+        std::filesystem::path("tantivy_wal_recovery_after_commits_test.jsonl");
+    // NOTE: Simulates a crash by restoring the log after the pending changes
+    // are rolled back.
+    auto crash = [&](mgcxx::text_search::Context context) {
+      std::filesystem::copy_file(
+          wal_path, crashed_wal_path,
+          std::filesystem::copy_options::overwrite_existing);
+      mgcxx::text_search::close_index(std::move(context), false);
+      std::filesystem::copy_file(
+          crashed_wal_path, wal_path,
+          std::filesystem::copy_options::overwrite_existing);
+      std::filesystem::remove(crashed_wal_path);
+    };
+
+    auto context = mgcxx::text_search::create_index(index_name, config);
+    auto docs = dummy_data1(6, 1);
+    mgcxx::text_search::add_document(context, docs[0], true);
+    mgcxx::text_search::commit(context);
+    mgcxx::text_search::add_document(context, docs[1], true);
+    mgcxx::text_search::commit(context);
+    // NOTE: Operations logged after the commits belong to a new generation of
+    // the log -> they are replayed.
+    mgcxx::text_search::add_document(context, docs[2], true);
+    mgcxx::text_search::add_document(context, docs[3], true);
+    crash(std::move(context));
+    context = mgcxx::text_search::create_index(index_name, config);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 4);
+
+    // NOTE: The replay is committed as well -> logging goes on after it.
+    mgcxx::text_search::add_document(context, docs[4], true);
+    mgcxx::text_search::add_document(context, docs[5], true);
+    crash(std::move(context));
+    context = mgcxx::text_search::create_index(index_name, config);
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 6);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, auto_commit_test) {
+  try {
+    auto index_name = "tantivy_index_auto_commit_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{
+            .mappings = dummy_mappings1().dump(),
+            .settings = R"({"auto_commit": {"max_pending": 2, "interval_ms": 100}})"});
+    auto docs = dummy_data1(3, 1);
+    mgcxx::text_search::add_document(context, docs[0], true);
+    mgcxx::text_search::add_document(context, docs[1], true);
+    mgcxx::text_search::add_document(context, docs[2], true);
+
+    // NOTE: The first two documents are committed because of max_pending, the
+    // last one once it's pending for interval_ms.
+    auto deadline = std::chrono::steady_clock::now() + std::chrono::seconds(5);
+    while (mgcxx::text_search::get_num_docs(context) < 3 &&
+           std::chrono::steady_clock::now() < deadline) {
+      std::this_thread::sleep_for(std::chrono::milliseconds(10));
+    }
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 3);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, commit_async_test) {
+  try {
+    auto index_name = "tantivy_index_commit_async_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()});
+    for (const auto &doc : dummy_data1(5, 1)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    auto handle = mgcxx::text_search::commit_async(context);
+    // NOTE: The rollback waits for the commit, so nothing is discarded.
+    mgcxx::text_search::rollback(context);
+    ASSERT_GT(handle->wait(), 0);
+    ASSERT_TRUE(handle->is_done());
+    ASSERT_EQ(handle->error(), "");
+    ASSERT_EQ(mgcxx::text_search::get_num_docs(context), 5);
+    mgcxx::text_search::drop_index(std::move(context));
+
+    // NOTE: A failed commit is reported through the handle.
+    context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()});
+    std::filesystem::remove_all(index_name);
+    for (const auto &doc : dummy_data1(5, 1)) {
+      mgcxx::text_search::add_document(context, doc, true);
+    }
+    handle = mgcxx::text_search::commit_async(context);
+    EXPECT_THROW(handle->wait(), ::rust::Error);
+    ASSERT_TRUE(handle->is_done());
+    ASSERT_NE(handle->error(), "");
+    std::filesystem::create_directory(index_name);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, cancellation_test) {
+  try {
+    auto index_name = "tantivy_index_cancellation_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()});
+    for (const auto &doc : dummy_data1(5, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:value0",
+        .return_fields = {"data"}};
+    auto token = mgcxx::text_search::create_cancellation_token();
+    ASSERT_EQ(
+        mgcxx::text_search::search_cancellable(context, search_input, *token)
+            .docs.size(),
+        5);
+
+    token->cancel();
+    ASSERT_TRUE(token->is_cancelled());
+    EXPECT_THROW(
+        mgcxx::text_search::search_cancellable(context, search_input, *token),
+        ::rust::Error);
+    search_input.partial_results = true;
+    auto partial_output =
+        mgcxx::text_search::search_cancellable(context, search_input, *token);
+    ASSERT_EQ(partial_output.docs.size(), 0);
+    ASSERT_EQ(partial_output.warnings.size(), 1);
+    ASSERT_EQ(
+        mgcxx::text_search::count_cancellable(context, search_input, *token),
+        0);
+
+    search_input.partial_results = false;
+    EXPECT_THROW(
+        mgcxx::text_search::count_cancellable(context, search_input, *token),
+        ::rust::Error);
+    auto searcher = mgcxx::text_search::acquire_searcher(context);
+    EXPECT_THROW(searcher->search_cancellable(search_input, *token),
+                 ::rust::Error);
+    EXPECT_THROW(searcher->count_cancellable(search_input, *token),
+                 ::rust::Error);
+    mgcxx::text_search::release_searcher(std::move(searcher));
+    auto transaction = mgcxx::text_search::begin_transaction(context);
+    EXPECT_THROW(transaction->search_cancellable(search_input, *token),
+                 ::rust::Error);
+    EXPECT_THROW(transaction->count_cancellable(search_input, *token),
+                 ::rust::Error);
+    mgcxx::text_search::rollback_transaction(std::move(transaction));
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, timeout_test) {
+  try {
+    auto index_name = "tantivy_index_timeout_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{.mappings = dummy_mappings1().dump()});
+    constexpr uint64_t docs_no = 5;
+    for (const auto &doc : dummy_data1(docs_no, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+
+    // NOTE: The timeout is long enough for the search to finish.
+    mgcxx::text_search::SearchInput search_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:\"value0 is AWESOME\"",
+        .count_total = true,
+        .timeout_ms = 60000};
+    auto output = mgcxx::text_search::search(context, search_input);
+    ASSERT_EQ(output.total_count, docs_no);
+    ASSERT_EQ(output.warnings.size(), 0);
+    ASSERT_EQ(mgcxx::text_search::count(context, search_input), docs_no);
+
+    // NOTE: A token cancelled before the call interrupts the search the same
+    // way as an expired timeout, before anything is collected.
+    auto token = mgcxx::text_search::create_cancellation_token();
+    token->cancel();
+    EXPECT_THROW(
+        mgcxx::text_search::search_cancellable(context, search_input, *token),
+        ::rust::Error);
+    EXPECT_THROW(
+        mgcxx::text_search::count_cancellable(context, search_input, *token),
+        ::rust::Error);
+
+    search_input.partial_results = true;
+    auto partial_output =
+        mgcxx::text_search::search_cancellable(context, search_input, *token);
+    ASSERT_EQ(partial_output.total_count, 0);
+    ASSERT_EQ(partial_output.warnings.size(), 1);
+    ASSERT_EQ(
+        mgcxx::text_search::count_cancellable(context, search_input, *token),
+        0);
+
+    // NOTE: The regex stops enumerating the matching terms as well.
+    mgcxx::text_search::SearchInput regex_input = {.search_fields = {"data"},
+                                                   .search_query = ".*",
+                                                   .count_total = true};
+    ASSERT_EQ(mgcxx::text_search::regex_search(context, regex_input).total_count,
+              docs_no);
+    EXPECT_THROW(
+        mgcxx::text_search::regex_search_cancellable(context, regex_input,
+                                                     *token),
+        ::rust::Error);
+    regex_input.partial_results = true;
+    partial_output = mgcxx::text_search::regex_search_cancellable(
+        context, regex_input, *token);
+    ASSERT_EQ(partial_output.total_count, 0);
+    ASSERT_EQ(partial_output.warnings.size(), 1);
+    mgcxx::text_search::drop_index(std::move(context));
+  } catch (const ::rust::Error &error) {
+    FAIL() << "Test failed: " << error.what();
+  }
+}
+
+TEST(text_search_test_case, aggregation_limits_test) {
+  try {
+    auto index_name = "tantivy_index_aggregation_limits_test";
+    auto context = mgcxx::text_search::create_index(
+        index_name,
+        mgcxx::text_search::IndexConfig{
+            .mappings = dummy_mappings1().dump(),
+            .settings = R"({"aggregation_limits": {"bucket_limit": 3}})"});
+    for (const auto &doc : dummy_data1(5, 1)) {
+      mgcxx::text_search::add_document(context, doc, false);
+    }
+    nlohmann::json aggregation_query = {};
+    aggregation_query["gids"]["terms"]["field"] = "metadata.gid";
+    mgcxx::text_search::SearchInput aggregate_input = {
+        .search_fields = {"data"},
+        .search_query = "data.key0:value0",
+        .aggregation_query = aggregation_query.dump(),
+    };
+    // NOTE: Each gid is a separate bucket.
+    EXPECT_THROW(mgcxx::text_search::aggregate(context, aggregate_input),
+                 ::rust::Error);
+    aggregate_input.search_query = "metadata.gid:0";
+    auto aggregation_result = nlohmann::json::parse(
+        mgcxx::text_search::aggregate(context, aggregate_input).data);
+    ASSERT_EQ(aggregation_result["gids"]["buckets"].size(), 1);
     mgcxx::text_search::drop_index(std::move(context));
   } catch (const ::rust::Error &error) {
     FAIL() << "Test failed: " << error.what();
@@ -163,7 +1763,7 @@ TEST(text_search_test_case, limit_test) {
 int main(int argc, char *argv[]) {
   // init tantivy engine (actually logging setup, should be called once per
   // process, early)
-  mgcxx::text_search::init("todo");
+  mgcxx::text_search::init("", nullptr);
   ::testing::InitGoogleTest(&argc, argv);
   return RUN_ALL_TESTS();
 }